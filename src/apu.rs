@@ -0,0 +1,91 @@
+// サウンドのレジスタ（NR10～NR52・波形RAM）
+// 音は出さない、書き込んだ値を読み出せるようにし、NR52の下位4bit（発音中のチャンネル）を扱う
+// 長さカウンタは未実装のため、発音したチャンネルはDACを切るか電源を切るまで発音中のまま
+
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+const POWER: u8 = 1 << 7;
+const NR52: usize = 0x16;
+
+// 読み込むと1になるbit（書き込み専用・未使用のbit）、0xFF10～0xFF2F
+const READ_MASK: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,   // NR10～NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,   // 未使用、NR21～NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,   // NR30～NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,   // 未使用、NR41～NR44
+    0x00, 0x00, 0x70,               // NR50～NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+// チャンネル毎のDACの設定（NRx2、チャンネル3はNR30）と発音開始（NRx4）のレジスタ
+const CHANNELS: [(usize, usize); 4] = [(0x02, 0x04), (0x07, 0x09), (0x0A, 0x0E), (0x11, 0x13)];
+
+pub struct Apu {
+    regs: [u8; 0x20],       // 0xFF10～0xFF2F
+    wave: [u8; 0x10],       // 0xFF30～0xFF3F
+    channels: u8,           // 発音中のチャンネル（bit0～3）
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 0x20],
+            wave: [0; 0x10],
+            channels: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26          => READ_MASK[NR52] | self.regs[NR52] | self.channels,
+            0xFF10..=0xFF2F => READ_MASK[(addr - 0xFF10) as usize] | self.regs[(addr - 0xFF10) as usize],
+            0xFF30..=0xFF3F => self.wave[(addr - 0xFF30) as usize],
+            _               => panic!("Not Define {:x}", addr),
+        }
+    }
+
+    // 電源を切ると全てのレジスタが0になり、電源が入るまで波形RAM以外は書き込めない
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF30..=0xFF3F => self.wave[(addr - 0xFF30) as usize] = val,
+            0xFF26          => {
+                if val & POWER == 0 {
+                    self.regs = [0; 0x20];
+                    self.channels = 0;
+                }
+                self.regs[NR52] = val & POWER;
+            },
+            _ if self.regs[NR52] & POWER == 0 => (),
+            0xFF10..=0xFF2F => {
+                let reg = (addr - 0xFF10) as usize;
+                self.regs[reg] = val;
+                for (ch, &(dac, trigger)) in CHANNELS.iter().enumerate() {
+                    let dac_on = if ch == 2 { self.regs[dac] & 0x80 > 0 } else { self.regs[dac] & 0xF8 > 0 };
+                    if !dac_on {
+                        self.channels &= !(1 << ch);
+                    } else if reg == trigger && val & 0x80 > 0 {
+                        self.channels |= 1 << ch;
+                    }
+                }
+            },
+            _               => panic!("Not Define {:x}", addr),
+        }
+    }
+}
+
+impl Snapshot for Apu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+        w.bytes(&self.wave);
+        w.u8(self.channels);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.regs)?;
+        r.bytes_into(&mut self.wave)?;
+        self.channels = r.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+// 圧縮されたROMの読み込み
+// .zip は中の最初の .gb/.gbc（または名前を指定したもの）、.gz はそのまま展開する
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::inflate::inflate;
+
+const ZIP_LOCAL_HEADER: u32 = 0x04034B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014B50;
+const ZIP_END_OF_CENTRAL: u32 = 0x06054B50;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+fn u16_at(data: &[u8], pos: usize) -> io::Result<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("unexpected end of archive".to_string()))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> io::Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("unexpected end of archive".to_string()))
+}
+
+// ROMファイルを読み込む、圧縮されていなければそのまま
+pub fn load_rom(path: &Path, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if has_extension(path, "zip") {
+        unzip(&data, entry)
+    } else if has_extension(path, "gz") {
+        gunzip(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+// .sav などの名前の元になるパス、.gz は取り除く
+pub fn base_path(path: &Path) -> PathBuf {
+    if has_extension(path, "gz") {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+// 中央ディレクトリから対象のエントリを探して展開する
+fn unzip(data: &[u8], entry: Option<&str>) -> io::Result<Vec<u8>> {
+    // 終端レコードは末尾のコメントの前にある
+    let eocd = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| u32_at(data, pos).ok() == Some(ZIP_END_OF_CENTRAL))
+        .ok_or_else(|| invalid("zip end of central directory not found".to_string()))?;
+    let entries = u16_at(data, eocd + 10)? as usize;
+    let mut pos = u32_at(data, eocd + 16)? as usize;
+
+    for _ in 0..entries {
+        if u32_at(data, pos)? != ZIP_CENTRAL_HEADER {
+            return Err(invalid("broken zip central directory".to_string()));
+        }
+        let method = u16_at(data, pos + 10)?;
+        let crc = u32_at(data, pos + 16)?;
+        let compressed_size = u32_at(data, pos + 20)? as usize;
+        let name_len = u16_at(data, pos + 28)? as usize;
+        let extra_len = u16_at(data, pos + 30)? as usize;
+        let comment_len = u16_at(data, pos + 32)? as usize;
+        let local = u32_at(data, pos + 42)? as usize;
+        let name = data.get(pos + 46..pos + 46 + name_len)
+            .map(String::from_utf8_lossy)
+            .ok_or_else(|| invalid("broken zip central directory".to_string()))?;
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let matched = match entry {
+            Some(entry) => name == entry,
+            None        => {
+                let name = Path::new(name.as_ref());
+                has_extension(name, "gb") || has_extension(name, "gbc")
+            },
+        };
+        if !matched {
+            continue;
+        }
+
+        // ローカルヘッダの後ろにデータがある
+        if u32_at(data, local)? != ZIP_LOCAL_HEADER {
+            return Err(invalid("broken zip local header".to_string()));
+        }
+        let start = local + 30 + u16_at(data, local + 26)? as usize + u16_at(data, local + 28)? as usize;
+        let compressed = data.get(start..start + compressed_size)
+            .ok_or_else(|| invalid("unexpected end of archive".to_string()))?;
+        let rom = match method {
+            0 => compressed.to_vec(),
+            8 => inflate(compressed)?,
+            _ => return Err(invalid(format!("unsupported zip compression method {}", method))),
+        };
+        if crc32fast::hash(&rom) != crc {
+            return Err(invalid(format!("crc mismatch in {}", name)));
+        }
+        return Ok(rom);
+    }
+    Err(invalid(match entry {
+        Some(entry) => format!("{} not found in zip", entry),
+        None        => "no .gb/.gbc file in zip".to_string(),
+    }))
+}
+
+// gzip（RFC1952）の展開
+fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    if data.len() < 18 || data[0..3] != [0x1F, 0x8B, 0x08] {
+        return Err(invalid("not a gzip file".to_string()));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA > 0 {
+        pos += 2 + u16_at(data, pos)? as usize;
+    }
+    // ファイル名とコメントは0終端
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag > 0 {
+            pos += data.get(pos..)
+                .and_then(|rest| rest.iter().position(|&c| c == 0))
+                .ok_or_else(|| invalid("broken gzip header".to_string()))? + 1;
+        }
+    }
+    if flags & FHCRC > 0 {
+        pos += 2;
+    }
+
+    let rom = inflate(data.get(pos..data.len() - 8).unwrap_or_default())?;
+    let crc = u32_at(data, data.len() - 8)?;
+    let size = u32_at(data, data.len() - 4)?;
+    if crc32fast::hash(&rom) != crc || rom.len() as u32 != size {
+        return Err(invalid("gzip crc mismatch".to_string()));
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 無圧縮ブロックだけのDeflate
+    fn deflate_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let chunks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            out.push((i == chunks.len() - 1) as u8);
+            out.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1F, 0x8B, 0x08, 0x08, 0, 0, 0, 0, 0, 0xFF];
+        out.extend_from_slice(b"test.gb\0");
+        out.extend(deflate_stored(data));
+        out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    // 名前と中身の組からzipを作る、.txt は無圧縮、それ以外はDeflate
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for &(name, data) in entries {
+            let (method, compressed) = if name.ends_with(".txt") { (0u16, data.to_vec()) } else { (8, deflate_stored(data)) };
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);      // 時刻
+            fields.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+            fields.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0; 2]);      // 拡張フィールドの長さ
+
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&[0; 10]);    // コメントの長さ～外部属性
+            central.extend_from_slice(&(out.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+
+            out.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            out.extend_from_slice(&[20, 0, 0, 0]);
+            out.extend_from_slice(&fields);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&compressed);
+        }
+        let offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&ZIP_END_OF_CENTRAL.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&[0; 2]);
+        out
+    }
+
+    fn rom() -> Vec<u8> {
+        (0..0x20000u32).map(|i| (i * 7 + (i >> 9)) as u8).collect()
+    }
+
+    // 一時ファイルに書いて読み込む
+    fn load(name: &str, data: &[u8], entry: Option<&str>) -> io::Result<Vec<u8>> {
+        let path = std::env::temp_dir().join(format!("gb-archive-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        let ret = load_rom(&path, entry);
+        fs::remove_file(&path).unwrap();
+        ret
+    }
+
+    #[test]
+    fn plain_file_unchanged() {
+        let rom = rom();
+        assert_eq!(load("plain.gb", &rom, None).unwrap(), rom);
+        // 拡張子が違えば中身がgzipでもそのまま
+        assert_eq!(load("plain.gbc", &gzip(&rom), None).unwrap(), gzip(&rom));
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let rom = rom();
+        assert_eq!(load("rom.gb.gz", &gzip(&rom), None).unwrap(), rom);
+        assert_eq!(base_path(Path::new("dir/rom.gb.gz")), Path::new("dir/rom.gb"));
+
+        let mut broken = gzip(&rom);
+        let len = broken.len();
+        broken[len - 8] ^= 0xFF;
+        assert!(gunzip(&broken).is_err());
+        assert!(gunzip(&broken[..len / 2]).is_err());
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let rom = rom();
+        let other = vec![0x55; 0x8000];
+        let data = zip(&[("readme.txt", b"not a rom"), ("game.gb", &rom), ("other.gbc", &other)]);
+        assert_eq!(load("roms.zip", &data, None).unwrap(), rom);
+        assert_eq!(unzip(&data, Some("other.gbc")).unwrap(), other);
+        assert_eq!(unzip(&data, Some("readme.txt")).unwrap(), b"not a rom");
+        assert!(unzip(&data, Some("missing.gb")).is_err());
+        assert!(unzip(&zip(&[("readme.txt", b"not a rom")]), None).is_err());
+        assert!(unzip(&data[..data.len() / 2], None).is_err());
+    }
+}
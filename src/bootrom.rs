@@ -1,11 +1,56 @@
 // ブート用ROM
 // ROMデータは下記のものを使用
 // https://github.com/take44444/Gameboy-free_bootrom
+// 外部のブートROM（DMG・MGB・SGBは256byte、CGBは2304byte）も読み込める
+// 既知のイメージ以外は --any-bootrom を指定した場合のみ使う
 #![allow(dead_code)]
 
+use std::{error, fmt, io};
+
+use sha1_smol::Sha1;
+
+use crate::{
+    model::Model,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+pub const DMG_BOOTROM_SIZE: usize = 0x100;
+pub const CGB_BOOTROM_SIZE: usize = 0x900;
+
+// 既知のブートROMのSHA-1
+const KNOWN_BOOTROMS: [(&str, &str); 7] = [
+    ("8bd501e31921e9601788316dbd3ce9833a97bcbc", "DMG0"),
+    ("4ed31ec6b0b175bb109c0eb5fd3d193da823339f", "DMG"),
+    ("4e68f9da03c310e84c523654b9026e51f26ce7f0", "MGB"),
+    ("aa2f50a77dfb4823da96ba99309085a3c6278515", "SGB"),
+    ("93407ea10d2f30ab96a314d8eca44fe160aea734", "SGB2"),
+    ("1293d68bf9643bc4f36954c1e80e38f39864528d", "CGB"),
+    ("fa5287e24b0fa533b3b5ef2b28a81245346c1a0f", "AGB"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BootromError {
+    InvalidSize(usize),
+    UnknownImage(String),               // SHA-1
+    ModelMismatch(usize, &'static str), // サイズ、機種名
+}
+
+impl fmt::Display for BootromError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidSize(size) => write!(f, "invalid boot rom size {} (expected {} or {})", size, DMG_BOOTROM_SIZE, CGB_BOOTROM_SIZE),
+            Self::UnknownImage(ref hash) => write!(f, "unknown boot rom image (SHA-1 {}, use --any-bootrom to load it anyway)", hash),
+            Self::ModelMismatch(size, model) => write!(f, "{} byte boot rom cannot be used on {} model", size, model),
+        }
+    }
+}
+
+impl error::Error for BootromError {}
+
 pub struct Bootrom {
     rom: Vec<u8>,           // u8の配列
     active: bool,           // ブートROMの有効無効
+    name: Option<&'static str>,     // 既知のイメージの場合は機種名
 }
 
 impl Bootrom {
@@ -47,7 +92,59 @@ impl Bootrom {
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0x50,
             ],
             active: true,
+            name: None,
+        }
+    }
+
+    // 外部イメージから作成、サイズが不正な場合と未知のイメージ（allow_unknown でなければ）はエラー
+    pub fn from_bytes(rom: Vec<u8>, allow_unknown: bool) -> Result<Self, BootromError> {
+        if rom.len() != DMG_BOOTROM_SIZE && rom.len() != CGB_BOOTROM_SIZE {
+            return Err(BootromError::InvalidSize(rom.len()));
+        }
+        let hash = Sha1::from(&rom).digest().to_string();
+        let name = KNOWN_BOOTROMS.iter().find(|(sha1, _)| *sha1 == hash).map(|&(_, name)| name);
+        if name.is_none() && !allow_unknown {
+            return Err(BootromError::UnknownImage(hash));
+        }
+        Ok(Self {
+            rom,
+            active: true,
+            name,
+        })
+    }
+
+    // 既知のイメージの機種名、内蔵ROMや未知のイメージは None
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    // 既知のイメージの機種
+    pub fn model(&self) -> Option<Model> {
+        self.name.and_then(Model::from_name)
+    }
+
+    // イメージのSHA-1（ムービーの照合用）
+    pub fn hash(&self) -> [u8; 20] {
+        Sha1::from(&self.rom).digest().bytes()
+    }
+
+    // CGB用のイメージか
+    pub fn is_cgb(&self) -> bool {
+        self.rom.len() == CGB_BOOTROM_SIZE
+    }
+
+    // 機種に合うサイズか（CGB・AGBは2304byte、それ以外は256byte）
+    pub fn check_model(&self, model: Model) -> Result<(), BootromError> {
+        if self.is_cgb() != model.is_cgb() {
+            return Err(BootromError::ModelMismatch(self.rom.len(), model.name()));
         }
+        Ok(())
+    }
+
+    // ブートROMから読むアドレスか
+    // CGBの 0x100～0x1FF はカートリッジのヘッダが見える
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.active && (addr as usize) < self.rom.len() && !(0x100..0x200).contains(&addr)
     }
 
     // データ読み取り
@@ -65,4 +162,44 @@ impl Bootrom {
         if val != 0 { self.active = false; }
     }
 
-}
\ No newline at end of file
+}
+
+// ROMの内容は保存せず、有効かどうかだけを保存する
+impl Snapshot for Bootrom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.active);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.active = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_images() {
+        assert_eq!(Bootrom::from_bytes(vec![0; 0x200], true).err(), Some(BootromError::InvalidSize(0x200)));
+        // 未知のイメージは指定した場合のみ
+        let hash = Sha1::from([0; DMG_BOOTROM_SIZE]).digest().to_string();
+        assert_eq!(Bootrom::from_bytes(vec![0; DMG_BOOTROM_SIZE], false).err(), Some(BootromError::UnknownImage(hash)));
+        assert!(Bootrom::from_bytes(vec![0; DMG_BOOTROM_SIZE], true).is_ok_and(|rom| rom.name().is_none()));
+    }
+
+    #[test]
+    fn size_and_model() {
+        let dmg = Bootrom::from_bytes(vec![0; DMG_BOOTROM_SIZE], true).unwrap();
+        let cgb = Bootrom::from_bytes(vec![0; CGB_BOOTROM_SIZE], true).unwrap();
+        for model in [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Sgb2] {
+            assert!(dmg.check_model(model).is_ok());
+            assert_eq!(cgb.check_model(model).err(), Some(BootromError::ModelMismatch(CGB_BOOTROM_SIZE, model.name())));
+        }
+        for model in [Model::Cgb, Model::Agb] {
+            assert!(cgb.check_model(model).is_ok());
+            assert_eq!(dmg.check_model(model).err(), Some(BootromError::ModelMismatch(DMG_BOOTROM_SIZE, model.name())));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// ポケットカメラに入力する画像と撮影した写真の書き出し
+
+use std::{io, path::Path};
+
+use crate::image;
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+// 写真はSRAMの 0x2000 から 0x1000 毎に30枚分並ぶ
+const PHOTO_SLOTS: usize = 30;
+const PHOTO_START: usize = 0x2000;
+const PHOTO_STRIDE: usize = 0x1000;
+const PHOTO_STATE: usize = 0x11B2;         // 各スロットの状態、0xFFは空き
+
+// カメラに映る画像（128x112、0が黒で255が白）
+pub trait ImageSource {
+    fn frame(&mut self) -> Vec<u8>;
+}
+
+// 画像ファイル、サイズが異なる場合は拡大縮小する
+pub struct ImageFile {
+    frame: Vec<u8>,
+}
+
+impl ImageFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (width, height, pixels) = image::load_gray(path)?;
+        let frame = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| {
+                let x = (i % CAMERA_WIDTH) * width / CAMERA_WIDTH;
+                let y = (i / CAMERA_WIDTH) * height / CAMERA_HEIGHT;
+                pixels[y * width + x]
+            })
+            .collect();
+        Ok(Self { frame })
+    }
+}
+
+impl ImageSource for ImageFile {
+    fn frame(&mut self) -> Vec<u8> {
+        self.frame.clone()
+    }
+}
+
+// テストパターン、撮影毎に1ピクセルずつ横に流れる
+#[derive(Clone, Copy)]
+pub enum Pattern {
+    Gradient,       // 左から右へ白から黒
+    Checkerboard,   // 8x8の市松模様
+    Bars,           // 4階調の縦縞
+}
+
+pub struct TestPattern {
+    pattern: Pattern,
+    count: usize,
+}
+
+impl TestPattern {
+    pub fn new(pattern: Pattern) -> Self {
+        Self { pattern, count: 0 }
+    }
+}
+
+impl ImageSource for TestPattern {
+    fn frame(&mut self) -> Vec<u8> {
+        let shift = self.count;
+        self.count = self.count.wrapping_add(1);
+        (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| {
+                let x = (i % CAMERA_WIDTH + shift) % CAMERA_WIDTH;
+                let y = i / CAMERA_WIDTH;
+                match self.pattern {
+                    Pattern::Gradient     => 255 - (x * 255 / (CAMERA_WIDTH - 1)) as u8,
+                    Pattern::Checkerboard => if ((x >> 3) ^ (y >> 3)) & 1 == 0 { 0xFF } else { 0x00 },
+                    Pattern::Bars         => [0xFF, 0xAA, 0x55, 0x00][x * 4 / CAMERA_WIDTH],
+                }
+            })
+            .collect()
+    }
+}
+
+// 画像ファイル名かテストパターン名からImageSourceを作成する
+pub fn open_source(name: &str) -> io::Result<Box<dyn ImageSource>> {
+    Ok(match name {
+        "gradient"     => Box::new(TestPattern::new(Pattern::Gradient)),
+        "checkerboard" => Box::new(TestPattern::new(Pattern::Checkerboard)),
+        "bars"         => Box::new(TestPattern::new(Pattern::Bars)),
+        _              => Box::new(ImageFile::open(Path::new(name))?),
+    })
+}
+
+// 2bppのタイル形式（16x14タイル）の画像をグレースケールに変換する
+pub fn decode_tiles(data: &[u8]) -> Vec<u8> {
+    (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % CAMERA_WIDTH, i / CAMERA_WIDTH);
+            let offset = ((y >> 3) * (CAMERA_WIDTH >> 3) + (x >> 3)) * 16 + (y & 7) * 2;
+            let bit = 7 - (x & 7);
+            let color = (((data[offset + 1] >> bit) & 1) << 1) | ((data[offset] >> bit) & 1);
+            [0xFF, 0xAA, 0x55, 0x00][color as usize]
+        })
+        .collect()
+}
+
+// SRAMに保存された写真を dir/photo_XX.png として書き出す、戻り値は枚数
+pub fn export_photos(sram: &[u8], dir: &Path) -> io::Result<usize> {
+    if sram.len() < PHOTO_START + PHOTO_SLOTS * PHOTO_STRIDE {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for slot in 0..PHOTO_SLOTS {
+        if sram[PHOTO_STATE + slot] == 0xFF {
+            continue;
+        }
+        let start = PHOTO_START + slot * PHOTO_STRIDE;
+        let pixels = decode_tiles(&sram[start..start + PHOTO_STRIDE]);
+        image::save_gray(&dir.join(format!("photo_{:02}.png", slot + 1)), CAMERA_WIDTH, CAMERA_HEIGHT, &pixels)?;
+        count += 1;
+    }
+    Ok(count)
+}
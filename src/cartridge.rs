@@ -1,9 +1,68 @@
+use std::{error, fmt, io, path::Path};
+use crate::{
+    camera::{self, ImageSource},
+    cheat::Cheats,
+    licensee,
+    mbc::{self, Mbc},
+    state::{Snapshot, StateReader, StateWriter},
+};
 
-use std::str;
-use crate::mbc::Mbc;
+// 任天堂ロゴ
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// カートリッジ読み込み時のエラー
+#[derive(Debug)]
+pub enum CartridgeError {
+    Truncated(usize),                                   // ファイルがヘッダより短い
+    HeaderChecksum { expected: u8, actual: u8 },        // ヘッダチェックサム不一致
+    UnknownMapper(u8),                                  // 未対応のカートリッジタイプ
+    InvalidRomSize(u8),
+    InvalidSramSize(u8),
+    RomSizeMismatch { header: usize, actual: usize },   // ヘッダとファイルのサイズが違う
+    NonAsciiTitle,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Truncated(len) => write!(f, "file is too short ({} bytes)", len),
+            Self::HeaderChecksum { expected, actual } =>
+                write!(f, "header checksum mismatch (expected {:02x}, actual {:02x})", expected, actual),
+            Self::UnknownMapper(code) => write!(f, "unknown cartridge type {:02x}", code),
+            Self::InvalidRomSize(code) => write!(f, "invalid rom size {:02x}", code),
+            Self::InvalidSramSize(code) => write!(f, "invalid sram size {:02x}", code),
+            Self::RomSizeMismatch { header, actual } =>
+                write!(f, "rom size mismatch (header {} bytes, file {} bytes)", header, actual),
+            Self::NonAsciiTitle => write!(f, "title is not ascii"),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {}
+
+// CGB対応
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CgbSupport {
+    None,
+    Compatible,     // 0x80 CGBの機能に対応（DMGでも動作）
+    Only,           // 0xC0 CGB専用
+}
+
+// 仕向地
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
 
 // カードリッジヘッダ
 #[repr(C)]
+#[derive(Clone)]
 pub struct CartridgeHeader {
     entry_point: [u8; 4],
     logo: [u8; 48],
@@ -24,36 +83,214 @@ pub struct CartridgeHeader {
 
 impl CartridgeHeader {
     fn new(data: [u8; 0x50]) -> Self {
-      let ret = unsafe {
+      unsafe {
         std::mem::transmute::<[u8; 0x50], Self>(data)
-      };
-      let mut chksum: u8 = 0;
-      for i in 0x34..0x4d {
-        chksum = chksum.wrapping_sub(data[i]).wrapping_sub(1);
       }
-      assert!(chksum == ret.header_checksum[0], "Checksum validation failed.");
-      ret
+    }
+
+    // ROMの 0x100-0x14F からヘッダを取り出す
+    pub fn from_rom(rom: &[u8]) -> Result<Self, CartridgeError> {
+        match rom.get(0x100..0x150) {
+            Some(data) => Ok(Self::new(data.try_into().unwrap())),
+            None       => Err(CartridgeError::Truncated(rom.len())),
+        }
+    }
+
+    // エントリポイント（0x100～0x103、通常は NOP; JP 0x0150）
+    pub fn entry_point(&self) -> [u8; 4] {
+        self.entry_point
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        self.header_checksum[0]
+    }
+
+    // ヘッダチェックサム計算
+    pub fn calc_header_checksum(&self) -> u8 {
+        [&self.title[..], &self.maker, &self.cgb_flag, &self.new_license, &self.sgb_flag,
+         &self.cartridge_type, &self.rom_size, &self.sram_size, &self.destination,
+         &self.old_license, &self.game_version]
+            .concat()
+            .iter()
+            .fold(0u8, |chksum, v| chksum.wrapping_sub(*v).wrapping_sub(1))
+    }
+
+    // グローバルチェックサム（ビッグエンディアン）
+    pub fn global_checksum(&self) -> u16 {
+        u16::from_be_bytes(self.global_checksum)
+    }
+
+    // グローバルチェックサム計算、チェックサム自身を除く全バイトの和
+    pub fn calc_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+            .fold(0u16, |sum, (_, v)| sum.wrapping_add(*v as u16))
+    }
+
+    // 任天堂ロゴが正しいか
+    pub fn logo_valid(&self) -> bool {
+        self.logo == NINTENDO_LOGO
+    }
+
+    pub fn rom_size_code(&self) -> u8 {
+        self.rom_size[0]
+    }
+
+    pub fn sram_size_code(&self) -> u8 {
+        self.sram_size[0]
     }
 
     // ROMサイズ計算
     // 32[kb] * 2^code = 2 ^ (15+code) 
-    fn rom_size(&self) -> usize {
-        if self.rom_size[0] > 0x09 {panic!("Invaild rom size");}
-        1 << (15 + self.rom_size[0]) 
+    pub fn rom_size(&self) -> Result<usize, CartridgeError> {
+        if self.rom_size[0] > 0x08 {
+            return Err(CartridgeError::InvalidRomSize(self.rom_size[0]));
+        }
+        Ok(1 << (15 + self.rom_size[0]))
+    }
+
+    // バッテリーバックアップの有無
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type[0],
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFC | 0xFD | 0xFE | 0xFF)
+    }
+
+    // 時計（RTC）の有無、MBC3+TIMERとHuC3
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type[0], 0x0F | 0x10 | 0xFE)
     }
 
     // SRAMサイズ
-    fn sram_size(&self) -> usize {
-        match self.rom_size[0] {
+    pub fn sram_size(&self) -> Result<usize, CartridgeError> {
+        Ok(match self.sram_size[0] {
             0x00 => 0,
             0x01 => 0x800,      // 2[kb]
             0x02 => 0x2000,     // 8[kb]
             0x03 => 0x8000,     // 32[kb]
             0x04 => 0x20000,    // 128[kb]
             0x05 => 0x10000,    // 64[kb]
-            _    => panic!("Invalid sram size"),
+            code => return Err(CartridgeError::InvalidSramSize(code)),
+        })
+    }
+
+    // タイトル、末尾の0は取り除く
+    pub fn title(&self) -> Result<String, CartridgeError> {
+        let len = self.title.iter().position(|&c| c == 0).unwrap_or(self.title.len());
+        let title = &self.title[..len];
+        if !title.is_ascii() {
+            return Err(CartridgeError::NonAsciiTitle);
+        }
+        Ok(String::from_utf8_lossy(title).to_string())
+    }
+
+    // 製造者コード（4文字）、無い場合はNone
+    // 旧ライセンシーコードが0x33かCGB対応の場合のみ、それ以外はタイトルの一部
+    pub fn manufacturer_code(&self) -> Option<String> {
+        let has_code = self.old_license[0] == 0x33 || self.cgb_support() != CgbSupport::None;
+        if has_code && self.maker.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            Some(String::from_utf8_lossy(&self.maker).to_string())
+        } else {
+            None
+        }
+    }
+
+    // 0x134～0x143 の合計、CGBのブートROMがDMG用ソフトのパレット選択に使う
+    pub fn title_checksum(&self) -> u8 {
+        [&self.title[..], &self.maker, &self.cgb_flag].concat().iter().fold(0u8, |sum, &c| sum.wrapping_add(c))
+    }
+
+    // 旧ライセンシーコード（0x14B）の値
+    pub fn old_licensee_code(&self) -> u8 {
+        self.old_license[0]
+    }
+
+    // ライセンシーコード、旧コードが0x33の場合は新コード（2文字）を使う
+    pub fn licensee_code(&self) -> String {
+        if self.old_license[0] == 0x33 {
+            String::from_utf8_lossy(&self.new_license).to_string()
+        } else {
+            format!("{:02X}", self.old_license[0])
+        }
+    }
+
+    // ライセンシー名
+    pub fn licensee_name(&self) -> Option<&'static str> {
+        if self.old_license[0] == 0x33 {
+            licensee::new_name(&self.licensee_code())
+        } else {
+            licensee::old_name(self.old_license[0])
+        }
+    }
+
+    // CGBフラグ（0x143）とSGBフラグ（0x146）の値
+    pub fn cgb_flag(&self) -> u8 {
+        self.cgb_flag[0]
+    }
+
+    pub fn sgb_flag(&self) -> u8 {
+        self.sgb_flag[0]
+    }
+
+    pub fn cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag[0] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _    => CgbSupport::None,
+        }
+    }
+
+    // SGBの機能に対応しているか（旧ライセンシーコードが0x33の場合のみ有効）
+    pub fn sgb_support(&self) -> bool {
+        self.sgb_flag[0] == 0x03 && self.old_license[0] == 0x33
+    }
+
+    pub fn destination(&self) -> Destination {
+        if self.destination[0] == 0x00 { Destination::Japan } else { Destination::Overseas }
+    }
+
+    pub fn cartridge_type(&self) -> u8 {
+        self.cartridge_type[0]
+    }
+
+    // カートリッジタイプの名前
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type[0] {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _    => "UNKNOWN",
         }
     }
+
+    pub fn game_version(&self) -> u8 {
+        self.game_version[0]
+    }
 }
 
 
@@ -61,60 +298,138 @@ impl CartridgeHeader {
 pub struct Cartridge {
   rom: Vec<u8>,
   pub sram: Vec<u8>,
+  pub header: CartridgeHeader,
   mbc: Mbc,
+  battery: bool,
+  pub cheats: Cheats,
 }
 
 impl Cartridge {
-    pub fn new (rom: Vec<u8>) -> Self {
-        let header = CartridgeHeader::new(rom[0x100..0x150].try_into().unwrap());
-        let title = str::from_utf8(&header.title).unwrap().trim_end_matches('\0').to_string();
-        let rom_size = header.rom_size();
-        let sram_size = header.sram_size();
+    // ROMからカートリッジを作成する
+    // 自作ROMなどヘッダチェックサムが正しくない場合は verify_checksum を false にする
+    pub fn from_bytes(rom: Vec<u8>, verify_checksum: bool) -> Result<Self, CartridgeError> {
+        let header = CartridgeHeader::from_rom(&rom)?;
+        let chksum = header.calc_header_checksum();
+        if verify_checksum && chksum != header.header_checksum[0] {
+            return Err(CartridgeError::HeaderChecksum { expected: header.header_checksum[0], actual: chksum });
+        }
+        let title = header.title()?;
+        let rom_size = header.rom_size()?;
+        if rom_size != rom.len() {
+            return Err(CartridgeError::RomSizeMismatch { header: rom_size, actual: rom.len() });
+        }
         let rom_banks = rom_size >> 14;     // ROMバンクは1つあたり16KB
-        let mbc = Mbc::new(header.cartridge_type[0], rom_banks);
+        let multicart = mbc::is_mbc1_multicart(&rom);
+        let mbc = Mbc::new(header.cartridge_type[0], rom_banks, header.sram_size()?, multicart)
+            .ok_or(CartridgeError::UnknownMapper(header.cartridge_type[0]))?;
+        let sram_size = mbc.sram_size(header.sram_size()?);
         println!("catridge info {{title:{}, type:{}, rom_size:{}, sram_size:{}}}",
             title,
-            match mbc {
-                Mbc::NoMbc {..} => "No Mbc",
-                Mbc::Mbc1 {..} => "MBC1",
-            },
+            mbc.name(),
             rom_size,
             sram_size,
         );
-        Self {
+        Ok(Self {
             rom,
-            sram: vec![0; sram_size].into(),
+            sram: vec![0; sram_size],
+            battery: header.has_battery(),
+            header,
             mbc,
-        }
+            cheats: Cheats::default(),
+        })
     }
     // カートリッジ読み込み
     pub fn read(&self , addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7FFF => self.rom[self.mbc.get_addr(addr) & (self.rom.len() - 1)],
-            0xA000..=0xBFFF => match self.mbc {
-                Mbc::NoMbc => self.sram[addr as usize & (self.sram.len() -1)],
-                Mbc::Mbc1 { ref sram_enable, ..} => if *sram_enable {
-                    self.sram[self.mbc.get_addr(addr) & (self.sram.len() -1)]
-                } else {
-                    0xFF
-                },    
-            }
+            0x0000..=0x7FFF => self.cheats.patch_rom(addr, self.mbc.read_rom(&self.rom, &self.sram, addr)),
+            0xA000..=0xBFFF => self.mbc.read_sram(&self.sram, addr),
             _ => panic!("Not Define {:x}", addr),
         }
     }
     // カートリッジ書き込み
     pub fn write(&mut self, addr: u16, val: u8) {
-        let sram_len = self.sram.len();
         match addr {
-            0x0000..=0x7FFF => self.mbc.write(addr, val),
-            0xA000..=0xBFFF => match self.mbc {
-                Mbc::NoMbc => self.sram[addr as usize & (sram_len -1)] = val,
-                Mbc::Mbc1 { ref sram_enable, ..} => if *sram_enable {
-                    self.sram[self.mbc.get_addr(addr) & (sram_len -1)] = val
-                },    
-            }
+            0x0000..=0x7FFF => self.mbc.write(&mut self.sram, addr, val),
+            0xA000..=0xBFFF => self.mbc.write_sram(&mut self.sram, addr, val),
             _ => panic!("Not Define {:x}", addr),
         }
     }
 
-}
\ No newline at end of file
+    // ROMデータ
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // 加速度センサーの値を設定する（MBC7のみ）
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mbc.set_accelerometer(x, y);
+    }
+
+    // バッテリーバックアップの有無
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    // .savに保存するデータ、RTCがあれば末尾に付ける
+    pub fn save_data(&self) -> Vec<u8> {
+        let mut ret = self.sram.clone();
+        if let Some(trailer) = self.mbc.rtc_trailer() {
+            ret.extend(trailer);
+        }
+        ret
+    }
+
+    // .savのデータを読み込む
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&data[..len]);
+        if data.len() > self.sram.len() {
+            self.mbc.load_rtc_trailer(&data[self.sram.len()..]);
+        }
+    }
+
+    // 赤外線LED（HuC1・HuC3のみ）
+    pub fn ir_led(&self) -> Option<bool> {
+        self.mbc.ir_led()
+    }
+
+    pub fn set_ir_light(&mut self, light: bool) {
+        self.mbc.set_ir_light(light);
+    }
+
+    pub fn has_camera(&self) -> bool {
+        self.mbc.has_camera()
+    }
+
+    // RTCの時刻（MBC3・HuC3のみ）
+    pub fn set_clock(&mut self, secs: u64) {
+        self.mbc.set_clock(secs);
+    }
+
+    pub fn start_clock(&mut self, secs: u64) {
+        self.mbc.start_clock(secs);
+    }
+
+    // カメラに入力する画像を設定する（ポケットカメラのみ）
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_camera_source(source);
+    }
+
+    // ポケットカメラで保存した写真を書き出す
+    pub fn export_camera_photos(&self, dir: &Path) -> io::Result<usize> {
+        camera::export_photos(&self.sram, dir)
+    }
+}
+
+// チートの設定は保存しない
+impl Snapshot for Cartridge {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.sram);
+        self.mbc.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.sram)?;
+        self.mbc.load_state(r)
+    }
+}
@@ -0,0 +1,215 @@
+// チートコード
+// ゲームジーニー：ROMの読み込みを置き換える（ABC-DEF-GHI、比較値なしは ABC-DEF）
+// ゲームシャーク：VBlank毎にRAMへ書き込む（ttvvaaaa、アドレスはリトルエンディアン）
+
+use std::{error, fmt, fs, io, path::Path};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheatError {
+    InvalidLength(usize),
+    InvalidDigit(char),
+    RomAddress(u16),        // ゲームジーニーはROM領域のみ
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidLength(len) => write!(f, "invalid code length {}", len),
+            Self::InvalidDigit(c)    => write!(f, "invalid hex digit '{}'", c),
+            Self::RomAddress(addr)   => write!(f, "address {:04x} is not in rom", addr),
+        }
+    }
+}
+
+impl error::Error for CheatError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cheat {
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,    // 元の値がこれと一致する場合のみ置き換える
+    },
+    GameShark {
+        kind: u8,               // 01: 通常、8x: WRAMバンク指定（CGB）
+        addr: u16,
+        value: u8,
+    },
+}
+
+// 16進数の各桁
+fn hex_digits(code: &str) -> Result<Vec<u8>, CheatError> {
+    code.chars()
+        .filter(|&c| c != '-')
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(CheatError::InvalidDigit(c)))
+        .collect()
+}
+
+impl Cheat {
+    // コードの解析、桁数で種類を判定する
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let d = hex_digits(code.trim())?;
+        match d.len() {
+            6 | 9 => {
+                // AB: 値、FCDE: アドレス（Fは0xFとXOR）
+                let value = (d[0] << 4) | d[1];
+                let addr = ((d[5] ^ 0xF) as u16) << 12 | (d[2] as u16) << 8 | (d[3] as u16) << 4 | d[4] as u16;
+                if addr >= 0x8000 {
+                    return Err(CheatError::RomAddress(addr));
+                }
+                // GI: 右に2bit回転して0xBAとXOR（Hは未使用）
+                let compare = (d.len() == 9).then(|| ((d[6] << 4) | d[8]).rotate_right(2) ^ 0xBA);
+                Ok(Self::GameGenie { addr, value, compare })
+            },
+            8 => {
+                let byte = |i: usize| (d[i] << 4) | d[i + 1];
+                Ok(Self::GameShark {
+                    kind: byte(0),
+                    value: byte(2),
+                    addr: u16::from_le_bytes([byte(4), byte(6)]),
+                })
+            },
+            len => Err(CheatError::InvalidLength(len)),
+        }
+    }
+}
+
+pub struct CheatCode {
+    pub code: String,
+    pub name: String,
+    pub cheat: Cheat,
+    pub enabled: bool,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    codes: Vec<CheatCode>,
+}
+
+impl Cheats {
+    // チートファイルの読み込み
+    // 1行に1コード、コードの後ろは説明、# はコメント、先頭が - のコードは無効の状態で読み込む
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut cheats = Self::default();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (enabled, line) = match line.strip_prefix('-') {
+                Some(rest) => (false, rest.trim_start()),
+                None       => (true, line),
+            };
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let index = cheats.add(code, name.trim())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?;
+            cheats.codes[index].enabled = enabled;
+        }
+        Ok(cheats)
+    }
+
+    // コードの追加、戻り値は番号
+    pub fn add(&mut self, code: &str, name: &str) -> Result<usize, CheatError> {
+        self.codes.push(CheatCode {
+            code: code.to_string(),
+            name: name.to_string(),
+            cheat: Cheat::parse(code)?,
+            enabled: true,
+        });
+        Ok(self.codes.len() - 1)
+    }
+
+    pub fn codes(&self) -> &[CheatCode] {
+        &self.codes
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(code) = self.codes.get_mut(index) {
+            code.enabled = enabled;
+        }
+    }
+
+    // 有効・無効の切り替え、戻り値は切り替え後の状態
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let code = self.codes.get_mut(index)?;
+        code.enabled = !code.enabled;
+        Some(code.enabled)
+    }
+
+    // ROMの読み込み値にゲームジーニーを適用する
+    pub fn patch_rom(&self, addr: u16, val: u8) -> u8 {
+        self.codes.iter()
+            .filter(|code| code.enabled)
+            .find_map(|code| match code.cheat {
+                Cheat::GameGenie { addr: a, value, compare } if a == addr && compare.is_none_or(|c| c == val) => Some(value),
+                _ => None,
+            })
+            .unwrap_or(val)
+    }
+
+    // VBlank毎に書き込むゲームシャークのコード（アドレス、値）
+    pub fn game_shark_writes(&self) -> Vec<(u16, u8)> {
+        self.codes.iter()
+            .filter(|code| code.enabled)
+            .filter_map(|code| match code.cheat {
+                Cheat::GameShark { addr, value, .. } => Some((addr, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_game_genie() {
+        assert_eq!(Cheat::parse("3E1-A7E"), Ok(Cheat::GameGenie { addr: 0x11A7, value: 0x3E, compare: None }));
+        assert_eq!(Cheat::parse(" 3e1-a7e "), Cheat::parse("3E1-A7E"));
+        assert_eq!(Cheat::parse("00A-17B-C49"), Ok(Cheat::GameGenie { addr: 0x4A17, value: 0x00, compare: Some(0xC8) }));
+        assert_eq!(Cheat::parse("00A17BC49"), Cheat::parse("00A-17B-C49"));
+    }
+
+    #[test]
+    fn parse_game_shark() {
+        assert_eq!(Cheat::parse("01FF34C1"), Ok(Cheat::GameShark { kind: 0x01, addr: 0xC134, value: 0xFF }));
+        assert_eq!(Cheat::parse("910A00D0"), Ok(Cheat::GameShark { kind: 0x91, addr: 0xD000, value: 0x0A }));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(Cheat::parse("001-234"), Err(CheatError::RomAddress(0xB123)));
+        assert_eq!(Cheat::parse("3E1-A7"), Err(CheatError::InvalidLength(5)));
+        assert_eq!(Cheat::parse("3E1-A7E-0"), Err(CheatError::InvalidLength(7)));
+        assert_eq!(Cheat::parse("01FF34C1AB"), Err(CheatError::InvalidLength(10)));
+        assert_eq!(Cheat::parse("3G1-A7E"), Err(CheatError::InvalidDigit('G')));
+        assert_eq!(Cheat::parse(""), Err(CheatError::InvalidLength(0)));
+    }
+
+    #[test]
+    fn game_genie_compare() {
+        let mut cheats = Cheats::default();
+        cheats.add("00A-17B-C49", "").unwrap();
+        cheats.add("3E1-A7E", "").unwrap();
+        // 元の値が比較値と一致する場合のみ置き換える
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0x00);
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC9), 0xC9);
+        assert_eq!(cheats.patch_rom(0x11A7, 0x12), 0x3E);
+        assert_eq!(cheats.patch_rom(0x11A8, 0x12), 0x12);
+
+        assert_eq!(cheats.toggle(0), Some(false));
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0xC8);
+        assert_eq!(cheats.toggle(2), None);
+    }
+
+    #[test]
+    fn game_shark_writes() {
+        let mut cheats = Cheats::default();
+        cheats.add("01FF34C1", "").unwrap();
+        cheats.add("3E1-A7E", "").unwrap();
+        cheats.add("010200C0", "").unwrap();
+        cheats.set_enabled(2, false);
+        assert_eq!(cheats.game_shark_writes(), vec![(0xC134, 0xFF)]);
+    }
+}
@@ -0,0 +1,299 @@
+// ゲームボーイ本体（CPUと周辺機器の組）
+#![allow(dead_code)]
+
+use std::io;
+
+use crate::{
+    bootrom::Bootrom,
+    cartridge::Cartridge,
+    cpu::{interrupts::JOYPAD, Cpu},
+    model::Model,
+    peripherals::Peripherals,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+// 1フレームのMサイクル数（154ライン x 114）
+pub const CYCLES_PER_FRAME: u32 = 17556;
+
+pub struct Console {
+    pub cpu: Cpu,
+    pub peripherals: Peripherals,
+    model: Model,
+    cycles: u64,        // 経過Mサイクル
+    frames: u64,        // 経過フレーム数
+    frame_cycles: u32,  // 今のフレームのMサイクル
+}
+
+impl Console {
+    pub fn new(model: Model, bootrom: Bootrom, cartridge: Cartridge) -> Self {
+        Self {
+            cpu: Cpu::new(),
+            peripherals: Peripherals::new(bootrom, cartridge, model),
+            model,
+            cycles: 0,
+            frames: 0,
+            frame_cycles: 0,
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // ブートROMを飛ばし、起動後の状態を再現する
+    pub fn skip_boot(&mut self) {
+        let header = &self.peripherals.cartridge.header;
+        let cgb_mode = self.model.cgb_mode(header);
+        self.cpu.set_registers(self.model.post_boot_registers(header, cgb_mode));
+        for (addr, val) in self.model.post_boot_io() {
+            self.peripherals.write(&mut self.cpu.interrupts, addr, val);
+        }
+        self.peripherals.timer.set_div(self.model.post_boot_div());
+        self.peripherals.ppu.skip_boot();
+    }
+
+    // 1Mサイクル進める、フレームが終われば true
+    // フレームはVBlankで終わる、画面が無効の間はフレーム分のサイクルで区切る
+    pub fn emulate_cycle(&mut self) -> bool {
+        self.cycles += 1;
+        self.frame_cycles += 1;
+        self.cpu.emulate_cycle(&mut self.peripherals);
+        self.peripherals.timer.emulate_cycle(&mut self.cpu.interrupts);
+        self.peripherals.serial.emulate_cycle(&mut self.cpu.interrupts);
+        // 相手の光はカートリッジの赤外線ポートにも届く
+        let light = self.peripherals.infrared.light();
+        self.peripherals.cartridge.set_ir_light(light);
+        if self.peripherals.ppu.emulate_cycle() {
+            // ゲームシャークはVBlank毎にRAMへ書き込む
+            for (addr, val) in self.peripherals.cartridge.cheats.game_shark_writes() {
+                self.peripherals.write(&mut self.cpu.interrupts, addr, val);
+            }
+            if let Some(ref mut sgb) = self.peripherals.sgb {
+                sgb.end_frame(&self.peripherals.ppu);
+            }
+        } else if self.frame_cycles < CYCLES_PER_FRAME {
+            return false;
+        }
+        self.frames += 1;
+        self.frame_cycles = 0;
+        true
+    }
+
+    // 1フレーム進める
+    pub fn run_frame(&mut self) {
+        while !self.emulate_cycle() {}
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // カートリッジのRTCを実時間の代わりに指定の時刻（UNIX時間[s]）で動かす
+    pub fn set_clock(&mut self, secs: u64) {
+        self.peripherals.cartridge.set_clock(secs);
+    }
+
+    // 電源投入時のRTCの時刻も指定の時刻にする
+    pub fn start_clock(&mut self, secs: u64) {
+        self.peripherals.cartridge.start_clock(secs);
+    }
+
+    // ボタン操作
+    pub fn press(&mut self, button: u8) {
+        if self.peripherals.joypad.press(button) {
+            self.cpu.interrupts.irq(JOYPAD);
+        }
+    }
+
+    pub fn release(&mut self, button: u8) {
+        self.peripherals.joypad.release(button);
+    }
+
+    // 押されているボタン
+    pub fn buttons(&self) -> u8 {
+        self.peripherals.joypad.pressed()
+    }
+
+    // ボタンの状態をまとめて設定する
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.release(!buttons);
+        self.press(buttons);
+    }
+}
+
+impl Snapshot for Console {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(self.model.name().as_bytes());
+        w.u64(self.cycles);
+        w.u64(self.frames);
+        w.u32(self.frame_cycles);
+        self.cpu.save_state(w);
+        self.peripherals.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        if r.bytes()? != self.model.name().as_bytes() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "save state was made with a different model"));
+        }
+        self.cycles = r.u64()?;
+        self.frames = r.u64()?;
+        self.frame_cycles = r.u32()?;
+        self.cpu.load_state(r)?;
+        self.peripherals.load_state(r)
+    }
+}
+
+// 通信ケーブルでつないだ本体を1Mサイクルずつ交互に進める、1台目のフレームが終われば true
+// 一方がHALTなどで止まっていても、もう一方は進む
+pub fn emulate_cycle_linked(consoles: &mut [Console]) -> bool {
+    let Some((first, rest)) = consoles.split_first_mut() else {
+        return false;
+    };
+    let frame = first.emulate_cycle();
+    for console in rest {
+        console.emulate_cycle();
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::NINTENDO_LOGO, serial::cable, state};
+
+    // プログラムを0x0150に置いたROMで本体を作る
+    fn console(prog: &[u8]) -> Console {
+        console_model(Model::Dmg, prog)
+    }
+
+    fn console_model(model: Model, prog: &[u8]) -> Console {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x150..0x150 + prog.len()].copy_from_slice(prog);
+        let cartridge = Cartridge::from_bytes(rom, false).unwrap();
+        let mut console = Console::new(model, Bootrom::new(), cartridge);
+        console.skip_boot();
+        console
+    }
+
+    fn read(console: &Console, addr: u16) -> u8 {
+        console.peripherals.read(&console.cpu.interrupts, addr)
+    }
+
+    // ブートROMを飛ばした後のI/Oレジスタは起動後の値と同じ
+    #[test]
+    fn post_boot_io() {
+        let common = [
+            (0xFF00, 0xCF), (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+            (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF40, 0x91), (0xFF47, 0xFC), (0xFFFF, 0x00),
+        ];
+        // DMG0はLYが0に変わる前に終わる
+        for (model, div, nr52, stat, ly) in [(Model::Dmg0, 0x18, 0xF1, 0x81, 153), (Model::Dmg, 0xAB, 0xF1, 0x85, 0), (Model::Sgb, 0x00, 0xF0, 0x85, 0)] {
+            let console = console_model(model, &[0x18, 0xFE]);
+            for (addr, val) in common.into_iter().chain([(0xFF04, div), (0xFF26, nr52), (0xFF41, stat), (0xFF44, ly)]) {
+                assert_eq!(read(&console, addr), val, "{} {:04X}", model.name(), addr);
+            }
+        }
+    }
+
+    // CALLとサブルーチンを繰り返し、WRAMに書き続ける
+    const CALL_LOOP: [u8; 19] = [
+        0x31, 0xFE, 0xFF,           // LD SP,0xFFFE
+        0x21, 0x00, 0xC0,           // LD HL,0xC000
+        0xCD, 0x60, 0x01,           // CALL 0x0160
+        0x18, 0xFB,                 // JR 0x0156
+        0x00, 0x00, 0x00, 0x00, 0x00,
+        0x3C,                       // INC A
+        0x22,                       // LD (HL+),A
+        0xC9,                       // RET
+    ];
+
+    // 命令の途中で保存しても、読み込んで進めると保存後にそのまま進めた場合と同じになる
+    #[test]
+    fn state_round_trip_mid_instruction() {
+        for start in 0..24 {
+            let mut console = console(&CALL_LOOP);
+            for _ in 0..start {
+                console.emulate_cycle();
+            }
+            let saved = state::save(&console);
+            for _ in 0..1000 {
+                console.emulate_cycle();
+            }
+            let expected = state::save(&console);
+
+            state::load(&mut console, &saved).unwrap();
+            assert_eq!(state::save(&console), saved, "start {}", start);
+            for _ in 0..1000 {
+                console.emulate_cycle();
+            }
+            assert_eq!(state::save(&console), expected, "start {}", start);
+            assert_eq!(read(&console, 0xC000), 0x02);     // Aは起動後0x01
+        }
+    }
+
+    // 途中で切れたステートや別のROMのステートは読み込まず、本体はそのまま
+    #[test]
+    fn state_load_rejects_invalid() {
+        let mut console = console(&CALL_LOOP);
+        for _ in 0..100 {
+            console.emulate_cycle();
+        }
+        let saved = state::save(&console);
+        for _ in 0..100 {
+            console.emulate_cycle();
+        }
+        let current = state::save(&console);
+
+        for len in [0, 8, saved.len() / 2, saved.len() - 1] {
+            assert!(state::load(&mut console, &saved[..len]).is_err(), "len {}", len);
+            assert_eq!(state::save(&console), current, "len {}", len);
+        }
+
+        let mut other = console_model(Model::Dmg, &[0x18, 0xFE]);
+        let before = state::save(&other);
+        assert!(state::load(&mut other, &saved).is_err());
+        assert_eq!(state::save(&other), before);
+    }
+
+    // 1Pは外部クロックでHALTして待ち、2Pが内部クロックで送る
+    #[test]
+    fn serial_transfer_while_halted() {
+        let p1 = console(&[
+            0x3E, 0x08, 0xE0, 0xFF,     // IE = シリアル
+            0x3E, 0x42, 0xE0, 0x01,     // SB = 0x42
+            0x3E, 0x80, 0xE0, 0x02,     // SC = 外部クロックで開始
+            0x76,                       // HALT
+            0x3E, 0x99, 0xEA, 0x00, 0xC0,   // (C000) = 0x99
+            0x18, 0xFE,
+        ]);
+        let p2 = console(&[
+            0x06, 0x40, 0x05, 0x20, 0xFD,   // 1Pが待つまで少し待つ
+            0x3E, 0x24, 0xE0, 0x01,     // SB = 0x24
+            0x3E, 0x81, 0xE0, 0x02,     // SC = 内部クロックで開始
+            0x18, 0xFE,
+        ]);
+        let mut consoles = [p1, p2];
+        let (cable1, cable2) = cable::pair();
+        consoles[0].peripherals.serial.connect(Box::new(cable1));
+        consoles[1].peripherals.serial.connect(Box::new(cable2));
+
+        for _ in 0..CYCLES_PER_FRAME {
+            emulate_cycle_linked(&mut consoles);
+        }
+        let [ref p1, ref p2] = consoles;
+        assert_eq!(p1.peripherals.read(&p1.cpu.interrupts, 0xFF01), 0x24);
+        assert_eq!(p1.peripherals.read(&p1.cpu.interrupts, 0xC000), 0x99);
+        assert_eq!(p2.peripherals.read(&p2.cpu.interrupts, 0xFF01), 0x42);
+        assert_eq!(p1.cycles(), p2.cycles());
+    }
+}
@@ -1,12 +1,8 @@
-//use std::io::{self};
-
-use std::sync::atomic::{
-    AtomicU8, Ordering::Relaxed      // 同期は行わない
-};
+use std::io;
 
 // CPU
 use crate::{
-    cpu::{interrupts::{Interrupts, JOYPAD, SERIAL, STAT, TIMER, VBLANK}, operand::{Cond, Direct8, Imm16, Imm8, Indirect, Reg16, Reg8, IO8}}, peripherals::Peripherals, registers::Registers
+    cpu::{interrupts::{Interrupts, JOYPAD, SERIAL, STAT, TIMER, VBLANK}, operand::{Cond, Direct8, Imm16, Imm8, Indirect, Reg16, Reg8, IO8}}, peripherals::Peripherals, registers::Registers, state::{Snapshot, StateReader, StateWriter}
 };
 
 mod operand;
@@ -17,12 +13,24 @@ const CHK_OP: u8 = 0x91;
 
 
 
+// 複数サイクルに渡る処理の途中状態
+#[derive(Default, Clone, Copy)]
+struct Step {
+    step: u8,
+    val8: u8,
+    val16: u16,
+}
+
 // 1サイクルで完了しない命令用
 #[derive(Default, Clone)]
 struct Ctx {
     opecode: u8,
     cb: bool,
     int: bool,          // 割り込みフラグ
+    inst: Step,         // 命令、割り込み
+    stack: Step,        // push16、pop16
+    operand: Step,      // オペランドの読み書き
+    imm: Step,          // Imm8の読み取り（Direct8の中からも呼ばれる）
 }
 
 #[derive(Default, Clone)]
@@ -43,6 +51,11 @@ impl Cpu {
         }
     }
 
+    // レジスタの設定（ブートROMを飛ばす場合）
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.regs = regs;
+    }
+
     // フェッチ
     pub fn fetch (&mut self, bus: &Peripherals) {
         self.ctx.opecode = bus.read(&self.interrupts, self.regs.pc);  // プログラムカウンタを格納
@@ -98,8 +111,7 @@ impl Cpu {
 
     // ISR
     fn call_isr(&mut self, bus: &mut Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(_) = self.push16(bus, self.regs.pc) {
                     // 割り込み優先順位が高いものを処理する、trailing_zerosは末尾の0の数を返す
@@ -114,12 +126,12 @@ impl Cpu {
                         JOYPAD => 0x0060,
                         _      => panic!("Not Define: {:x}", highest_int),
                     };
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                 }
             },
             1 => {
                 self.interrupts.ime = false;    // 割り込み無効
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!("Not Define"),
@@ -147,6 +159,7 @@ impl Cpu {
         // オペコードで分類
         match self.ctx.opecode {
             0x00 => self.nop(bus),
+            0x76 => self.halt(bus),
 
             0x06 => self.ld(bus, Reg8::B, Imm8),
             0x0E => self.ld(bus, Reg8::C, Imm8),
@@ -230,3 +243,45 @@ impl Cpu {
 
 }
 
+impl Snapshot for Step {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.step);
+        w.u8(self.val8);
+        w.u16(self.val16);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.step = r.u8()?;
+        self.val8 = r.u8()?;
+        self.val16 = r.u16()?;
+        Ok(())
+    }
+}
+
+// 命令の途中でも保存できるよう、途中状態も含める
+impl Snapshot for Cpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.cycle);
+        self.regs.save_state(w);
+        self.interrupts.save_state(w);
+        w.u8(self.ctx.opecode);
+        w.bool(self.ctx.cb);
+        w.bool(self.ctx.int);
+        for step in [&self.ctx.inst, &self.ctx.stack, &self.ctx.operand, &self.ctx.imm] {
+            step.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.cycle = r.u8()?;
+        self.regs.load_state(r)?;
+        self.interrupts.load_state(r)?;
+        self.ctx.opecode = r.u8()?;
+        self.ctx.cb = r.bool()?;
+        self.ctx.int = r.bool()?;
+        for step in [&mut self.ctx.inst, &mut self.ctx.stack, &mut self.ctx.operand, &mut self.ctx.imm] {
+            step.load_state(r)?;
+        }
+        Ok(())
+    }
+}
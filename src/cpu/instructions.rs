@@ -1,13 +1,3 @@
-
-
-//use std::sync::atomic::{
-//    AtomicU16, AtomicU8, Ordering::Relaxed      // 同期は行わない
-//};
-
-use core::sync::atomic::{
-    AtomicU16, AtomicU8, Ordering::Relaxed      // 同期は行わない
-};
-
 use crate::{
     cpu::{
         operand::{Cond, Imm16, Imm8, Reg16, IO16, IO8}, Cpu
@@ -26,18 +16,17 @@ impl Cpu {
     // HALT
     // 割り込みが発生するまでCPUを停止させる
     pub fn halt(&mut self, bus: &Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if self.interrupts.get_interrupt() > 0 {
                     self.fetch(bus);
                 } else {
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                 }
             },
             1 => {
                 if self.interrupts.get_interrupt() > 0 {
-                    STEP.store(0, Relaxed);
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             },
@@ -49,20 +38,18 @@ impl Cpu {
     pub fn ld<D: Copy, S: Copy> (&mut self, bus: &mut Peripherals, dst: D, src: S) 
     where Self: IO8<D> + IO8<S> {
         //println!("ld8");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, src) {
-                    VAL8.store(v, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val8 = v;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.ld(bus, dst, src);
                 }
             },
             1 => {
-                if self.write8(bus, dst, VAL8.load(Relaxed)).is_some() {
-                    STEP.store(0, Relaxed);
+                if self.write8(bus, dst, self.ctx.inst.val8).is_some() {
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             },
@@ -72,20 +59,18 @@ impl Cpu {
     pub fn ld16<D: Copy, S: Copy> (&mut self, bus: &mut Peripherals, dst: D, src: S) 
     where Self: IO16<D> + IO16<S> {
         //println!("[ld16]");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read16(bus, src) {
-                    VAL16.store(v, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val16 = v;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.ld16(bus, dst, src);
                 }
             },
             1 => {
-                if self.write16(bus, dst, VAL16.load(Relaxed)).is_some() {
-                    STEP.store(0, Relaxed);
+                if self.write16(bus, dst, self.ctx.inst.val16).is_some() {
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             },
@@ -127,9 +112,7 @@ impl Cpu {
     pub fn dec<S: Copy>(&mut self, bus: &mut Peripherals, src: S)
     where Self: IO8<S> 
     {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, src) {
                     let result = v.wrapping_sub(1);     // デクリメント
@@ -138,42 +121,41 @@ impl Cpu {
                     self.regs.set_nf(true);
                     self.regs.set_hf(v & 0xf == 0);
                     // 記録
-                    VAL8.store(result, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val8 = result;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.dec(bus, src);
                 }
             },
             1 => {
-                if self.write8(bus, src, VAL8.load(Relaxed)).is_some() {
-                    STEP.store(0, Relaxed);
+                if self.write8(bus, src, self.ctx.inst.val8).is_some() {
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             }
-            _ => panic!("Err dec :{}", STEP.load(Relaxed)),
+            _ => panic!("Err dec :{}", self.ctx.inst.step),
         }
     }
     pub fn dec16<S: Copy>(&mut self, bus: &mut Peripherals, src: S)
     where Self: IO16<S>
     {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read16(bus, src) {
-                    VAL16.store(v.wrapping_sub(1), Relaxed);
-                    STEP.store(1, Relaxed);
+                    bus.ppu.oam_bug(v);
+                    self.ctx.inst.val16 = v.wrapping_sub(1);
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.dec16(bus, src);
                 }
             },
             1 => {
-                if self.write16(bus, src, VAL16.load(Relaxed)).is_some() {
-                    STEP.store(2, Relaxed);
+                if self.write16(bus, src, self.ctx.inst.val16).is_some() {
+                    self.ctx.inst.step = 2;
                 }
             },
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!(""),
@@ -185,9 +167,7 @@ impl Cpu {
     pub fn inc<S: Copy>(&mut self, bus: &mut Peripherals, src: S)
     where Self: IO8<S> 
     {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, src) {
                     let result = v.wrapping_add(1);     // インクリメント
@@ -196,41 +176,40 @@ impl Cpu {
                     self.regs.set_nf(false);
                     self.regs.set_hf(v & 0xf == 0xf);
                     // 記録
-                    VAL8.store(result, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val8 = result;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.inc(bus, src);
                 }
             },
             1 => {
-                if self.write8(bus, src, VAL8.load(Relaxed)).is_some() {
-                    STEP.store(0, Relaxed);
+                if self.write8(bus, src, self.ctx.inst.val8).is_some() {
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             }
-            _ => panic!("Err dec :{}", STEP.load(Relaxed)),
+            _ => panic!("Err dec :{}", self.ctx.inst.step),
         }
     }
     pub fn inc16<S: Copy>(&mut self, bus: &mut Peripherals, src: S)
     where Self: IO16<S> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read16(bus, src) {
-                    VAL16.store(v.wrapping_add(1), Relaxed);
-                    STEP.store(1, Relaxed);
+                    bus.ppu.oam_bug(v);
+                    self.ctx.inst.val16 = v.wrapping_add(1);
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.inc16(bus, src);
                 }
             },
             1 => {
-                if self.write16(bus, src, VAL16.load(Relaxed)).is_some() {
-                    STEP.store(2, Relaxed);
+                if self.write16(bus, src, self.ctx.inst.val16).is_some() {
+                    self.ctx.inst.step = 2;
                 }
             },
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!(""),
@@ -240,18 +219,17 @@ impl Cpu {
     // SUB : Aレジスタからsの値を引く
     pub fn sub<S: Copy>(&mut self, bus: &Peripherals, src: S) 
     where Self: IO8<S> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, src) {
                     self.regs.a = self.regs.a.wrapping_sub(v);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.sub(bus, src);
                 }
             },
             1 => {
-                STEP.store(1, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!("Not Define"),
@@ -261,22 +239,21 @@ impl Cpu {
 
     // JR : プログラムカウンタに値を加算する
     pub fn jr(&mut self, bus: &Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, Imm8) {
                     self.regs.pc = self.regs.pc.wrapping_add(v as i8 as u16);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.jr(bus);
                 }
             },
             1 => {
                 // サイクル数+1
-                STEP.store(2, Relaxed);
+                self.ctx.inst.step = 2;
             }
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!(""),
@@ -295,26 +272,25 @@ impl Cpu {
     }
     pub fn jr_c (&mut self, bus: &Peripherals, c: Cond) {
         //println!("[jr_c]");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, Imm8) {
-                    STEP.store(2, Relaxed);
+                    self.ctx.inst.step = 2;
                     // 条件を満たしている場合はジャンプ、加えてサイクル+1
                     if self.cond(c) {
                         //println!("exec jr");
                         self.regs.pc = self.regs.pc.wrapping_add(v as i8 as u16);
-                        STEP.store(1, Relaxed);
+                        self.ctx.inst.step = 1;
                     }
                     // 応答が得られたので再度処理を行う
                     self.jr_c(bus, c);
                 }
             },
             1 => {
-                STEP.store(2, Relaxed);
+                self.ctx.inst.step = 2;
             },
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!(""),
@@ -327,9 +303,7 @@ impl Cpu {
     pub fn rl<S: Copy> (&mut self, bus: &mut Peripherals, src: S)
     where Self: IO8<S> {
         //println!("[rl]");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read8(bus, src) {
                     let result = (v << 1) | self.regs.cf() as u8;
@@ -337,15 +311,15 @@ impl Cpu {
                     self.regs.set_nf(false);
                     self.regs.set_hf(false);
                     self.regs.set_cf(v & 0x80 > 0);
-                    VAL8.store(result, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val8 = result;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.rl(bus, src);
                 }
             },
             1 => {
-                if self.write8(bus, src, VAL8.load(Relaxed)).is_some() {
-                    STEP.store(0, Relaxed);
+                if self.write8(bus, src, self.ctx.inst.val8).is_some() {
+                    self.ctx.inst.step = 0;
                     self.fetch(bus);
                 }
             }
@@ -357,13 +331,10 @@ impl Cpu {
     // push ：　16bit値をデクリメントした後にスタックポインタが指すアドレスに値を格納する
     pub fn push16 (&mut self, bus: &mut Peripherals, val: u16) -> Option<()> {
         //println!("[push16]");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.stack.step {
             0 => {
                 // pushはメモリアクセス数+1のサイクル数
-                STEP.store(1, Relaxed);
+                self.ctx.stack.step = 1;
                 None
             },
             1 => {
@@ -373,16 +344,16 @@ impl Cpu {
                 self.regs.sp = self.regs.sp.wrapping_sub(1);
                 bus.write(&mut self.interrupts, self.regs.sp, hi);
                 //
-                VAL8.store(lo, Relaxed);
-                STEP.store(2, Relaxed);
+                self.ctx.stack.val8 = lo;
+                self.ctx.stack.step = 2;
                 None
             },
             2 => {
                 // デクリメントしたアドレスに書き込み
                 self.regs.sp = self.regs.sp.wrapping_sub(1);
-                bus.write(&mut self.interrupts, self.regs.sp, VAL8.load(Relaxed));
+                bus.write(&mut self.interrupts, self.regs.sp, self.ctx.stack.val8);
                 //
-                STEP.store(0, Relaxed);
+                self.ctx.stack.step = 0;
                 Some(())
             },
             _ => panic!("Not implemented: push16"),
@@ -390,23 +361,21 @@ impl Cpu {
     }
     pub fn push (&mut self, bus: &mut Peripherals, src: Reg16) {
         //println!("push");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 // pushはレジスタ操作のみなのでサイクル消費しない
-                VAL16.store(self.read16(bus, src).unwrap(), Relaxed);
-                STEP.store(1, Relaxed);
+                self.ctx.inst.val16 = self.read16(bus, src).unwrap();
+                self.ctx.inst.step = 1;
                 // 応答が得られたので再度処理を行う
                 self.push(bus, src);
             },
             1 => {
-                if self.push16(bus, VAL16.load(Relaxed)).is_some() {
-                    STEP.store(2, Relaxed);
+                if self.push16(bus, self.ctx.inst.val16).is_some() {
+                    self.ctx.inst.step = 2;
                 }
             },
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!("Not implemented: push"),
@@ -417,26 +386,23 @@ impl Cpu {
 
     // pop d : 16bitの値をスタックからpopする
     pub fn pop16 (&mut self, bus: &Peripherals) -> Option<u16> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.stack.step {
             0 => {
-                VAL8.store(bus.read(&self.interrupts, self.regs.sp), Relaxed);
+                self.ctx.stack.val8 = bus.read(&self.interrupts, self.regs.sp);
                 self.regs.sp = self.regs.sp.wrapping_add(1);
-                STEP.store(1, Relaxed);
+                self.ctx.stack.step = 1;
                 None
             },
             1 => {
                 let hi = bus.read(&self.interrupts, self.regs.sp);
                 self.regs.sp = self.regs.sp.wrapping_add(1);
-                VAL16.store(u16::from_le_bytes([VAL8.load(Relaxed), hi]), Relaxed);
-                STEP.store(2, Relaxed);
+                self.ctx.stack.val16 = u16::from_le_bytes([self.ctx.stack.val8, hi]);
+                self.ctx.stack.step = 2;
                 None
             },
             2 => {
-                STEP.store(0, Relaxed);
-                Some(VAL16.load(Relaxed))
+                self.ctx.stack.step = 0;
+                Some(self.ctx.stack.val16)
             },
             _ => panic!(""),
         }
@@ -451,16 +417,15 @@ impl Cpu {
     // RET : return
     // 16bitの値をプログラムカウンタに代入する、4サイクル
     pub fn ret(&mut self, bus: &Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.pop16(bus) {
                     self.regs.pc = v;
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                 }
             },
             1 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!(""),
@@ -470,17 +435,16 @@ impl Cpu {
     // RETI
     // IMEを1にする以外はRETと同じ
     pub fn reti(&mut self, bus: &Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.pop16(bus) {
                     self.regs.pc = v;
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                 }
             },
             1 => {
                 self.interrupts.ime = true;             // retとの差
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!(""),
@@ -506,14 +470,12 @@ impl Cpu {
     // call ：　プログラムカウンタの値をスタックにpushし、その後元のプログラムカウンタに戻す、6サイクル
     pub fn call (&mut self, bus: &mut Peripherals) {
         //println!("call");
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 // プログラムカウンタの値取り出し
                 if let Some(v) = self.read16(bus, Imm16) {
-                    VAL16.store(v, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.val16 = v;
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.call(bus);
                 }
@@ -521,12 +483,12 @@ impl Cpu {
             1 => {
                 // プログラムカウンタの値をpush（3サイクル）
                 if self.push16(bus, self.regs.pc).is_some() {
-                    self.regs.pc = VAL16.load(Relaxed);
-                    STEP.store(2, Relaxed);
+                    self.regs.pc = self.ctx.inst.val16;
+                    self.ctx.inst.step = 2;
                 }
             },
             2 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!("Not implemented: call"),
@@ -536,16 +498,15 @@ impl Cpu {
     // RST
     // 指定されたアドレスを対象にCALLを行う、4サイクル
     pub fn rst(&mut self, bus: &mut Peripherals, addr: u8) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if self.push16(bus, self.regs.pc).is_some() {
                     self.regs.pc = addr as u16;
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                 }
             },
             1 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             },
             _ => panic!("Not Define"),
@@ -555,18 +516,17 @@ impl Cpu {
 
     // JP
     pub fn jp(&mut self, bus: &Peripherals) {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.inst.step {
             0 => {
                 if let Some(v) = self.read16(bus, Imm16) {
                     self.regs.pc = v;
-                    STEP.store(1, Relaxed);
+                    self.ctx.inst.step = 1;
                     // 応答が得られたので再度処理を行う
                     self.jp(bus);
                 }
             },
             1 => {
-                STEP.store(0, Relaxed);
+                self.ctx.inst.step = 0;
                 self.fetch(bus);
             }
             _ => panic!(""),
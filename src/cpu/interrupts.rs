@@ -1,3 +1,7 @@
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
 
 pub const VBLANK: u8 = 1 << 0;      // PPUがモード1に入るたびに呼ばれる
 pub const STAT: u8 = 1 << 1;        // PPUに要求される
@@ -43,3 +47,17 @@ impl Interrupts {
     }
 }
 
+impl Snapshot for Interrupts {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.ime);
+        w.u8(self.int_flags);
+        w.u8(self.int_enables);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ime = r.bool()?;
+        self.int_flags = r.u8()?;
+        self.int_enables = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,13 +1,6 @@
 
 #![allow(dead_code)]
 
-use std::sync::atomic::{
-    AtomicU8,
-    AtomicU16,
-    Ordering::Relaxed,      // 同期は行わない
-};
-
-
 use crate::{
     cpu::Cpu,
     peripherals::Peripherals,
@@ -103,18 +96,16 @@ impl IO16<Reg16> for Cpu {
 // プログラムカウンタが指す場所から読み取られる8bit、サイクル1消費
 impl IO8<Imm8> for Cpu {
     fn read8(&mut self, bus: &Peripherals, _: Imm8) -> Option<u8> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.imm.step {
             0 => {
-                VAL8.store(bus.read(&self.interrupts, self.regs.pc), Relaxed);    // プログラムカウンタの場所を読み取り
+                self.ctx.imm.val8 = bus.read(&self.interrupts, self.regs.pc);    // プログラムカウンタの場所を読み取り
                 self.regs.pc = self.regs.pc.wrapping_add(1);    // プログラムカウンタ増加
-                STEP.store(1, Relaxed);
+                self.ctx.imm.step = 1;
                 None
             },
             1 => {
-                STEP.store(0, Relaxed);
-                Some(VAL8.load(Relaxed))    // 応答
+                self.ctx.imm.step = 0;
+                Some(self.ctx.imm.val8)    // 応答
             },
             _ => panic!(""),
         }
@@ -129,27 +120,24 @@ impl IO8<Imm8> for Cpu {
 // プログラムカウンタが指す場所から読み取られる16bit、サイクル2消費
 impl IO16<Imm16> for Cpu {
     fn read16(&mut self, bus: &Peripherals, _: Imm16) -> Option<u16> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.operand.step {
             0 => {
-                VAL8.store(bus.read(&self.interrupts,self.regs.pc), Relaxed);    // プログラムカウンタの場所を読み取り
+                self.ctx.operand.val8 = bus.read(&self.interrupts,self.regs.pc);    // プログラムカウンタの場所を読み取り
                 self.regs.pc = self.regs.pc.wrapping_add(1);    // プログラムカウンタ増加
-                STEP.store(1, Relaxed);
+                self.ctx.operand.step = 1;
                 None
             },
             1 => {
                 let hi = bus.read(&self.interrupts,self.regs.pc);    // プログラムカウンタの場所を読み取り
                 self.regs.pc = self.regs.pc.wrapping_add(1);    // プログラムカウンタ増加
-                VAL16.store(u16::from_le_bytes([VAL8.load(Relaxed), hi]), Relaxed);
-                STEP.store(2, Relaxed);
+                self.ctx.operand.val16 = u16::from_le_bytes([self.ctx.operand.val8, hi]);
+                self.ctx.operand.step = 2;
                 None
             },
             2 => {
                 // 応答
-                STEP.store(0, Relaxed);
-                Some(VAL16.load(Relaxed))
+                self.ctx.operand.step = 0;
+                Some(self.ctx.operand.val16)
             },
             _ => panic!(""),
         }
@@ -165,11 +153,9 @@ impl IO16<Imm16> for Cpu {
 // 16bitレジスタ、もしくは2つの8bitレジスタからなる16bitが指す場所の8bitを読み取る、サイクル1消費
 impl IO8<Indirect> for Cpu {
     fn read8 (&mut self, bus: &Peripherals, src: Indirect) -> Option<u8> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.operand.step {
             0 => {
-                VAL8.store(match  src{
+                self.ctx.operand.val8 = match  src{
                     Indirect::BC  => bus.read(&self.interrupts, self.regs.bc()),
                     Indirect::DE  => bus.read(&self.interrupts, self.regs.de()),
                     Indirect::HL  => bus.read(&self.interrupts, self.regs.hl()),
@@ -186,22 +172,20 @@ impl IO8<Indirect> for Cpu {
                         self.regs.write_hl(addr.wrapping_add(1));
                         bus.read(&self.interrupts, addr)
                     },
-                }, Relaxed);
-                STEP.store(1, Relaxed);
+                };
+                self.ctx.operand.step = 1;
                 None
             },
             1 => {
-                STEP.store(0, Relaxed);
-                Some(VAL8.load(Relaxed))
+                self.ctx.operand.step = 0;
+                Some(self.ctx.operand.val8)
             },
             _ => panic!("Not implemented: Indirect read"),
         }
     }
 
     fn write8(&mut self, bus: &mut Peripherals, dst: Indirect, val: u8) -> Option<()> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.operand.step {
             0 => {
                 match dst {
                     Indirect::BC  => bus.write(&mut self.interrupts, self.regs.bc(), val),
@@ -223,11 +207,11 @@ impl IO8<Indirect> for Cpu {
 
                     },
                 }
-                STEP.store(1, Relaxed);
+                self.ctx.operand.step = 1;
                 None
             },
             1 => {
-                STEP.store(0, Relaxed);
+                self.ctx.operand.step = 0;
                 Some(())
             },
             _ => panic!("Not implemented: Indirect Indirect"),
@@ -240,73 +224,67 @@ impl IO8<Indirect> for Cpu {
 // Dの場合は3サイクル、DFFは2サイクル
 impl IO8<Direct8> for Cpu {
     fn read8(&mut self, bus: &Peripherals, src: Direct8) -> Option<u8> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.operand.step {
             0 => {
                 if let Some(lo) = self.read8(bus, Imm8) {
-                    VAL8.store(lo, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.operand.val8 = lo;
+                    self.ctx.operand.step = 1;
                     // DFFか？その場合はサイクル数が1少ない
                     if let Direct8::DFF = src {
-                        VAL16.store(0xFF00 | (lo as u16), Relaxed);
-                        STEP.store(2, Relaxed);
+                        self.ctx.operand.val16 = 0xFF00 | (lo as u16);
+                        self.ctx.operand.step = 2;
                     }
                 }
                 None
             },
             1 => {
                 if let Some(hi) = self.read8(bus, Imm8) {
-                    VAL16.store(u16::from_le_bytes([VAL8.load(Relaxed), hi]), Relaxed);
-                    STEP.store(2, Relaxed);
+                    self.ctx.operand.val16 = u16::from_le_bytes([self.ctx.operand.val8, hi]);
+                    self.ctx.operand.step = 2;
                 }
                 None
             },
             2 => {
-                VAL8.store(bus.read(&self.interrupts, VAL16.load(Relaxed)), Relaxed);
-                STEP.store(3, Relaxed);
+                self.ctx.operand.val8 = bus.read(&self.interrupts, self.ctx.operand.val16);
+                self.ctx.operand.step = 3;
                 None
             },
             3 => {
-                STEP.store(0, Relaxed);
-                Some(VAL8.load(Relaxed))
+                self.ctx.operand.step = 0;
+                Some(self.ctx.operand.val8)
             }
             _ => panic!(""),
         }
     }
 
     fn write8(&mut self, bus: &mut Peripherals, dst: Direct8, val: u8) -> Option<()> {
-        static STEP: AtomicU8 = AtomicU8::new(0);
-        static VAL8: AtomicU8 = AtomicU8::new(0);
-        static VAL16: AtomicU16 = AtomicU16::new(0);
-        match STEP.load(Relaxed) {
+        match self.ctx.operand.step {
             0 => {
                 if let Some(lo) = self.read8(bus, Imm8) {
-                    VAL8.store(lo, Relaxed);
-                    STEP.store(1, Relaxed);
+                    self.ctx.operand.val8 = lo;
+                    self.ctx.operand.step = 1;
                     // DFFか？その場合はサイクル数が1少ない
                     if let Direct8::DFF = dst {
-                        VAL16.store(0xFF00 | (lo as u16), Relaxed);
-                        STEP.store(2, Relaxed);
+                        self.ctx.operand.val16 = 0xFF00 | (lo as u16);
+                        self.ctx.operand.step = 2;
                     }
                 }
                 None
             },
             1 => {
                 if let Some(hi) = self.read8(bus, Imm8) {
-                    VAL16.store(u16::from_le_bytes([VAL8.load(Relaxed), hi]), Relaxed);
-                    STEP.store(2, Relaxed);
+                    self.ctx.operand.val16 = u16::from_le_bytes([self.ctx.operand.val8, hi]);
+                    self.ctx.operand.step = 2;
                 }
                 None
             },
             2 => {
-                bus.write(&mut self.interrupts,VAL16.load(Relaxed), val);
-                STEP.store(3, Relaxed);
+                bus.write(&mut self.interrupts,self.ctx.operand.val16, val);
+                self.ctx.operand.step = 3;
                 None
             },
             3 => {
-                STEP.store(0, Relaxed);
+                self.ctx.operand.step = 0;
                 Some(())
             }
             _ => panic!(""),
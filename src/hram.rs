@@ -1,5 +1,9 @@
 // 128byteのRAM
 
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
 
 pub struct HRam {
     hram: Vec<u8>,           // u8の配列
@@ -24,4 +28,14 @@ impl HRam {
   pub fn write(&mut self, addr: u16, val: u8) {
     self.hram[(addr as usize) & 0x7f] = val;
   }
-}
\ No newline at end of file
+}
+
+impl Snapshot for HRam {
+  fn save_state(&self, w: &mut StateWriter) {
+    w.bytes(&self.hram);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+    r.bytes_into(&mut self.hram)
+  }
+}
@@ -0,0 +1,125 @@
+// 画像ファイルの読み書き
+// 拡張子が .bmp ならBMP、それ以外はPNGとして扱う
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use tinybmp::{Bpp, RawBmp};
+
+fn is_bmp(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bmp"))
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+// RGBからグレースケールへの変換
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+// RGB555をRGB888に変換
+pub fn rgb888(c: u16) -> [u8; 3] {
+    let expand = |v: u16| ((v << 3) | (v >> 2)) as u8;
+    [expand(c & 0x1F), expand((c >> 5) & 0x1F), expand((c >> 10) & 0x1F)]
+}
+
+// グレースケール画像として読み込む、戻り値は（幅, 高さ, 画素）
+pub fn load_gray(path: &Path) -> io::Result<(usize, usize, Vec<u8>)> {
+    if is_bmp(path) {
+        load_bmp(&fs::read(path)?)
+    } else {
+        load_png(File::open(path)?)
+    }
+}
+
+fn load_bmp(data: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    let bmp = RawBmp::from_slice(data).map_err(|e| invalid_data(format!("{:?}", e)))?;
+    let (width, height) = (bmp.size().width as usize, bmp.size().height as usize);
+    let mut pixels = vec![0; width * height];
+    for p in bmp.pixels() {
+        let c = p.color;
+        pixels[p.position.y as usize * width + p.position.x as usize] = match bmp.color_bpp() {
+            Bpp::Bits24 | Bpp::Bits32 => luma((c >> 16) as u8, (c >> 8) as u8, c as u8),
+            Bpp::Bits16 => luma(((c >> 11) << 3) as u8, ((c >> 5) << 2) as u8, (c << 3) as u8),
+            _ => c as u8,
+        };
+    }
+    Ok((width, height, pixels))
+}
+
+fn load_png(file: File) -> io::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(invalid_data)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(invalid_data)?;
+    let channels = info.color_type.samples();
+    let pixels = buf[..info.buffer_size()]
+        .chunks(channels)
+        .map(|px| if channels >= 3 { luma(px[0], px[1], px[2]) } else { px[0] })
+        .collect();
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+// グレースケール画像を保存する
+pub fn save_gray(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    save(path, width, height, pixels, png::ColorType::Grayscale)
+}
+
+// RGB888の画像を保存する
+pub fn save_rgb(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    save(path, width, height, pixels, png::ColorType::Rgb)
+}
+
+fn save(path: &Path, width: usize, height: usize, pixels: &[u8], color: png::ColorType) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    if is_bmp(path) {
+        write_bmp(&mut w, width, height, pixels, color.samples())?;
+    } else {
+        let mut encoder = png::Encoder::new(&mut w, width as u32, height as u32);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels))
+            .map_err(io::Error::other)?;
+    }
+    w.flush()
+}
+
+// 24bitのBMPとして書き込む、行は下から順に4byte境界で並ぶ
+// 画素は1byte（グレースケール）か3byte（RGB）
+fn write_bmp<W: Write>(w: &mut W, width: usize, height: usize, pixels: &[u8], channels: usize) -> io::Result<()> {
+    let stride = (width * 3 + 3) & !3;
+    let image_size = (stride * height) as u32;
+    w.write_all(b"BM")?;
+    w.write_all(&(54 + image_size).to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(&54u32.to_le_bytes())?;
+    w.write_all(&40u32.to_le_bytes())?;
+    w.write_all(&(width as i32).to_le_bytes())?;
+    w.write_all(&(height as i32).to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&24u16.to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(&image_size.to_le_bytes())?;
+    w.write_all(&[0; 16])?;
+    let mut row = vec![0; stride];
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let px = &pixels[(y * width + x) * channels..][..channels];
+            if channels >= 3 {
+                // BMPはBGRの順
+                row[x * 3..x * 3 + 3].copy_from_slice(&[px[2], px[1], px[0]]);
+            } else {
+                row[x * 3..x * 3 + 3].fill(px[0]);
+            }
+        }
+        w.write_all(&row)?;
+    }
+    Ok(())
+}
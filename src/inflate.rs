@@ -0,0 +1,269 @@
+// Deflate（RFC1951）の展開
+// zip・gzipに格納されたROMの読み込みに使う
+
+use std::io;
+
+// 長さ・距離の基本値と追加ビット数
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// 符号長の符号長が並ぶ順番
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("inflate: {}", msg))
+}
+
+// LSBから順にビットを読む
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,         // ビット単位の位置
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u8) -> io::Result<u32> {
+        let mut ret = 0;
+        for i in 0..n {
+            let byte = *self.data.get(self.pos >> 3).ok_or_else(|| invalid("unexpected end of data"))?;
+            ret |= (((byte >> (self.pos & 7)) & 1) as u32) << i;
+            self.pos += 1;
+        }
+        Ok(ret)
+    }
+
+    // 次のバイト境界へ
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) & !7;
+    }
+}
+
+// 正規ハフマン符号
+struct Huffman {
+    counts: [u16; 16],      // 符号長毎の符号の数
+    symbols: Vec<u16>,      // 符号順に並べたシンボル
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        lengths.iter().for_each(|&len| counts[len as usize] += 1);
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    // 1bitずつ読みながら符号長毎に範囲を確認する
+    fn decode(&self, br: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= br.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid huffman code"))
+    }
+}
+
+// 展開
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut br = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = br.bits(1)? == 1;
+        match br.bits(2)? {
+            0 => stored(&mut br, &mut out)?,
+            1 => {
+                let (lit, dist) = fixed_tables();
+                codes(&mut br, &mut out, &lit, &dist)?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut br)?;
+                codes(&mut br, &mut out, &lit, &dist)?;
+            },
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// 無圧縮ブロック
+fn stored(br: &mut BitReader, out: &mut Vec<u8>) -> io::Result<()> {
+    br.align();
+    let len = br.bits(16)? as usize;
+    let nlen = br.bits(16)? as usize;
+    if len != !nlen & 0xFFFF {
+        return Err(invalid("stored block length mismatch"));
+    }
+    let start = br.pos >> 3;
+    let block = br.data.get(start..start + len).ok_or_else(|| invalid("unexpected end of data"))?;
+    out.extend_from_slice(block);
+    br.pos += len * 8;
+    Ok(())
+}
+
+// 固定ハフマン符号
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+// 動的ハフマン符号
+fn dynamic_tables(br: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let nlen = br.bits(5)? as usize + 257;
+    let ndist = br.bits(5)? as usize + 1;
+    let ncode = br.bits(4)? as usize + 4;
+
+    let mut lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(ncode) {
+        lengths[i] = br.bits(3)? as u8;
+    }
+    let code_huffman = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let sym = code_huffman.decode(br)?;
+        let (val, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeat with no previous length"))?, 3 + br.bits(2)?),
+            17 => (0, 3 + br.bits(3)?),
+            _  => (0, 11 + br.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(val, repeat as usize));
+    }
+    if lengths.len() > nlen + ndist {
+        return Err(invalid("too many lengths"));
+    }
+    Ok((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+}
+
+// 圧縮されたブロックの展開
+fn codes(br: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+    loop {
+        let sym = lit.decode(br)? as usize;
+        match sym {
+            0..=255 => out.push(sym as u8),
+            256     => return Ok(()),
+            _       => {
+                let i = sym - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length symbol"));
+                }
+                let len = LENGTH_BASE[i] as usize + br.bits(LENGTH_EXTRA[i])? as usize;
+                let d = dist.decode(br)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(invalid("invalid distance symbol"));
+                }
+                let distance = DIST_BASE[d] as usize + br.bits(DIST_EXTRA[d])? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+                // 重なりがあるので1byteずつコピー
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "hello hello hello hello" を固定ハフマン符号で圧縮したもの
+    const FIXED: [u8; 10] = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+
+    // a～zを3回、z～aを3回並べたものを動的ハフマン符号で圧縮したもの
+    const DYNAMIC: [u8; 55] = [
+        0x9D, 0xC9, 0xB7, 0x01, 0x00, 0x20, 0x08, 0x00, 0xB0, 0x5B, 0xB1, 0x63, 0x45, 0xEC, 0x5E, 0xEF,
+        0x0F, 0x66, 0x0D, 0x08, 0xA9, 0xB4, 0xB1, 0x0E, 0x7D, 0x88, 0x29, 0x17, 0xAA, 0xDC, 0xFA, 0x98,
+        0x6B, 0x9F, 0x0B, 0x1F, 0x73, 0xCF, 0x5E, 0x73, 0xF4, 0xC6, 0x95, 0x4A, 0x4E, 0x31, 0x78, 0x74,
+        0xD6, 0x68, 0x25, 0x05, 0xFC, 0xCC, 0x03,
+    ];
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xFA, 0xFF, b'h', b'e', b'l', b'l', b'o']).unwrap(), b"hello");
+        assert_eq!(inflate(&[0x01, 0x00, 0x00, 0xFF, 0xFF]).unwrap(), b"");
+    }
+
+    #[test]
+    fn fixed_block() {
+        assert_eq!(inflate(&FIXED).unwrap(), b"hello hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        let expected = [b"abcdefghijklmnopqrstuvwxyz".repeat(3), b"zyxwvutsrqponmlkjihgfedcba".repeat(3)].concat();
+        assert_eq!(inflate(&DYNAMIC).unwrap(), expected);
+    }
+
+    // 最後でない無圧縮ブロックの後に固定ハフマン符号のブロック
+    #[test]
+    fn multiple_blocks() {
+        let data = [&[0x00, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'][..], &FIXED].concat();
+        assert_eq!(inflate(&data).unwrap(), b"abchello hello hello hello");
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(inflate(&[]).is_err());
+        assert!(inflate(&[0x07]).is_err());                             // ブロックの種類が3
+        assert!(inflate(&[0x01, 0x05, 0x00, 0x00, 0x00]).is_err());     // LENとNLENが合わない
+        assert!(inflate(&[0x01, 0x05, 0x00, 0xFA, 0xFF, b'h']).is_err());
+        assert!(inflate(&[0x03, 0x02, 0x00]).is_err());                 // 出力より前を参照する
+        assert!(inflate(&[0x00, 0x00, 0x00, 0xFF, 0xFF]).is_err());     // 最後のブロックが無い
+    }
+
+    // 途中で切れたデータはエラー、壊れたデータでもpanicしない
+    #[test]
+    fn truncated_or_corrupted() {
+        for data in [&FIXED[..], &DYNAMIC] {
+            for len in 0..data.len() {
+                assert!(inflate(&data[..len]).is_err(), "len {}", len);
+            }
+            for pos in 0..data.len() {
+                for mask in [0x01, 0x10, 0x80, 0xFF] {
+                    let mut data = data.to_vec();
+                    data[pos] ^= mask;
+                    let _ = inflate(&data);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// gb-emu info <rom> [--json]
+// カートリッジヘッダの内容とファイルのハッシュを表示する
+
+use std::path::Path;
+
+use sha1_smol::Sha1;
+
+use crate::{
+    archive,
+    cartridge::{CartridgeHeader, CgbSupport, Destination},
+    mbc::Mbc,
+};
+
+enum Value {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    fn opt_str(val: Option<&str>) -> Self {
+        val.map_or(Value::Null, |v| Value::Str(v.to_string()))
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Value::Str(s)  => format!("\"{}\"", escape_json(s)),
+            Value::Num(n)  => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null    => "null".to_string(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Str(s)  => s.clone(),
+            Value::Num(n)  => n.to_string(),
+            Value::Bool(b) => if *b { "yes".to_string() } else { "no".to_string() },
+            Value::Null    => "-".to_string(),
+        }
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '"'  => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+            c    => c.to_string(),
+        })
+        .collect()
+}
+
+// ヘッダとファイルの情報を項目毎に集める
+fn collect(rom: &[u8], header: &CartridgeHeader) -> Vec<(&'static str, Value)> {
+    let mapper = Mbc::new(header.cartridge_type(), header.rom_size().unwrap_or(0) >> 14, 0, false);
+    vec![
+        ("entry_point", Value::Str(header.entry_point().iter().map(|b| format!("{:02x}", b)).collect())),
+        ("title", header.title().map_or(Value::Null, Value::Str)),
+        ("manufacturer_code", header.manufacturer_code().map_or(Value::Null, Value::Str)),
+        ("licensee_code", Value::Str(header.licensee_code())),
+        ("old_licensee_code", Value::Num(header.old_licensee_code() as u64)),
+        ("licensee", Value::opt_str(header.licensee_name())),
+        ("cgb", Value::Str(match header.cgb_support() {
+            CgbSupport::None       => "none",
+            CgbSupport::Compatible => "compatible",
+            CgbSupport::Only       => "only",
+        }.to_string())),
+        ("cgb_flag", Value::Num(header.cgb_flag() as u64)),
+        ("sgb", Value::Bool(header.sgb_support())),
+        ("sgb_flag", Value::Num(header.sgb_flag() as u64)),
+        ("cartridge_type", Value::Num(header.cartridge_type() as u64)),
+        ("cartridge_type_name", Value::Str(header.cartridge_type_name().to_string())),
+        ("mapper_supported", Value::Bool(mapper.is_some())),
+        ("battery", Value::Bool(header.has_battery())),
+        ("rtc", Value::Bool(header.has_rtc())),
+        ("rom_size_code", Value::Num(header.rom_size_code() as u64)),
+        ("rom_size", header.rom_size().map_or(Value::Null, |v| Value::Num(v as u64))),
+        ("sram_size_code", Value::Num(header.sram_size_code() as u64)),
+        ("sram_size", header.sram_size().map_or(Value::Null, |v| Value::Num(v as u64))),
+        ("destination", Value::Str(match header.destination() {
+            Destination::Japan    => "japan",
+            Destination::Overseas => "overseas",
+        }.to_string())),
+        ("version", Value::Num(header.game_version() as u64)),
+        ("header_checksum", Value::Str(format!("{:02x}", header.header_checksum()))),
+        ("header_checksum_valid", Value::Bool(header.calc_header_checksum() == header.header_checksum())),
+        ("global_checksum", Value::Str(format!("{:04x}", header.global_checksum()))),
+        ("global_checksum_valid", Value::Bool(CartridgeHeader::calc_global_checksum(rom) == header.global_checksum())),
+        ("logo_valid", Value::Bool(header.logo_valid())),
+        ("file_size", Value::Num(rom.len() as u64)),
+        ("crc32", Value::Str(format!("{:08x}", crc32fast::hash(rom)))),
+        ("sha1", Value::Str(Sha1::from(rom).digest().to_string())),
+    ]
+}
+
+// サブコマンドの実行、戻り値は終了コード
+pub fn run<I: Iterator<Item = String>>(args: I) -> i32 {
+    let mut path = None;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _        => path = Some(arg),
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("usage: gb-emu info <rom> [--json]");
+        return 1;
+    };
+
+    let rom = match archive::load_rom(Path::new(&path), None) {
+        Ok(rom) => rom,
+        Err(e)  => {
+            eprintln!("Cannot open {}: {}", path, e);
+            return 1;
+        },
+    };
+    let header = match CartridgeHeader::from_rom(&rom) {
+        Ok(header) => header,
+        Err(e)     => {
+            eprintln!("Cannot read header of {}: {}", path, e);
+            return 1;
+        },
+    };
+
+    let items = collect(&rom, &header);
+    if json {
+        let fields: Vec<String> = items.iter()
+            .map(|(key, val)| format!("  \"{}\": {}", key, val.to_json()))
+            .collect();
+        println!("{{\n{}\n}}", fields.join(",\n"));
+    } else {
+        for (key, val) in &items {
+            println!("{:<24}{}", format!("{}:", key), val.to_text());
+        }
+    }
+    0
+}
@@ -0,0 +1,131 @@
+// 赤外線通信（CGBのRPレジスタ、HuC1・HuC3カートリッジ）
+// RP bit0: LED、bit1: 受光（0で受光）、bit6・7: 両方1で受光を読み取れる
+#![allow(dead_code)]
+
+use std::{cell::RefCell, io, rc::Rc};
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// 赤外線の相手
+pub trait IrDevice {
+    // こちらのLEDの点灯・消灯
+    fn set_led(&mut self, on: bool);
+
+    // 相手の光を受けているか
+    fn light(&self) -> bool;
+}
+
+// 何も無い（受光しない）
+pub struct NoIr;
+
+impl IrDevice for NoIr {
+    fn set_led(&mut self, _: bool) {}
+
+    fn light(&self) -> bool {
+        false
+    }
+}
+
+// 自分のLEDの光を受ける（テスト用）
+#[derive(Default)]
+pub struct Loopback {
+    led: bool,
+}
+
+impl IrDevice for Loopback {
+    fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+
+    fn light(&self) -> bool {
+        self.led
+    }
+}
+
+// 同じプロセス内の2台を向かい合わせる
+pub struct IrEnd {
+    leds: Rc<RefCell<[bool; 2]>>,
+    side: usize,
+}
+
+pub fn pair() -> (IrEnd, IrEnd) {
+    let leds = Rc::new(RefCell::new([false; 2]));
+    (IrEnd { leds: leds.clone(), side: 0 }, IrEnd { leds, side: 1 })
+}
+
+impl IrDevice for IrEnd {
+    fn set_led(&mut self, on: bool) {
+        self.leds.borrow_mut()[self.side] = on;
+    }
+
+    fn light(&self) -> bool {
+        self.leds.borrow()[1 - self.side]
+    }
+}
+
+pub struct Infrared {
+    enabled: bool,      // RPはCGBモードのみ
+    rp: u8,
+    cart_led: bool,     // HuC1・HuC3のLED
+    device: Box<dyn IrDevice>,
+}
+
+impl Infrared {
+    pub fn new(cgb_mode: bool) -> Self {
+        Self {
+            enabled: cgb_mode,
+            rp: 0,
+            cart_led: false,
+            device: Box::new(NoIr),
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn IrDevice>) {
+        self.device = device;
+    }
+
+    // 受光しているか（カートリッジ側の読み込み用）
+    pub fn light(&self) -> bool {
+        self.device.light()
+    }
+
+    // カートリッジのLEDが変わった
+    pub fn set_cart_led(&mut self, on: bool) {
+        self.cart_led = on;
+        self.update_led();
+    }
+
+    fn update_led(&mut self) {
+        let on = self.rp & 0x01 != 0 || self.cart_led;
+        self.device.set_led(on);
+    }
+
+    pub fn read(&self, _: u16) -> u8 {
+        if !self.enabled {
+            return 0xFF;
+        }
+        let receiving = self.rp & 0xC0 == 0xC0 && self.device.light();
+        0x3C | (self.rp & 0xC1) | if receiving { 0x00 } else { 0x02 }
+    }
+
+    pub fn write(&mut self, _: u16, val: u8) {
+        if self.enabled {
+            self.rp = val & 0xC1;
+            self.update_led();
+        }
+    }
+}
+
+impl Snapshot for Infrared {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.rp);
+        w.bool(self.cart_led);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.rp = r.u8()?;
+        self.cart_led = r.bool()?;
+        self.update_led();
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+// ジョイパッド（P1レジスタ）
+// bit5: ボタン選択、bit4: 方向キー選択（0で選択）、下位4bitは押されたボタンが0になる
+#![allow(dead_code)]
+
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+pub const RIGHT: u8 = 1 << 0;
+pub const LEFT: u8 = 1 << 1;
+pub const UP: u8 = 1 << 2;
+pub const DOWN: u8 = 1 << 3;
+pub const A: u8 = 1 << 4;
+pub const B: u8 = 1 << 5;
+pub const SELECT: u8 = 1 << 6;
+pub const START: u8 = 1 << 7;
+
+const SELECT_DIRECTION: u8 = 1 << 4;
+const SELECT_ACTION: u8 = 1 << 5;
+
+pub struct Joypad {
+    select: u8,         // P1のbit4・bit5
+    pressed: u8,        // 押されているボタン（上位4bitがボタン、下位4bitが方向キー）
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_DIRECTION | SELECT_ACTION,
+            pressed: 0,
+        }
+    }
+
+    // P1読み込み
+    pub fn read(&self, _: u16) -> u8 {
+        let mut low = 0x0F;
+        if self.select & SELECT_DIRECTION == 0 {
+            low &= !self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTION == 0 {
+            low &= !(self.pressed >> 4) & 0x0F;
+        }
+        0xC0 | self.select | low
+    }
+
+    // P1書き込み、選択bitのみ書き込める
+    pub fn write(&mut self, _: u16, val: u8) {
+        self.select = val & (SELECT_DIRECTION | SELECT_ACTION);
+    }
+
+    // 選択bitの取得（SGBのパケット受信用）
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    // ボタンを押す、選択中の行が1から0に変われば割り込み要求（true）
+    pub fn press(&mut self, button: u8) -> bool {
+        let before = self.read(0xFF00) & 0x0F;
+        self.pressed |= button;
+        let after = self.read(0xFF00) & 0x0F;
+        before & !after != 0
+    }
+
+    pub fn release(&mut self, button: u8) {
+        self.pressed &= !button;
+    }
+
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.select);
+        w.u8(self.pressed);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.select = r.u8()?;
+        self.pressed = r.u8()?;
+        Ok(())
+    }
+}
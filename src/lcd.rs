@@ -1,35 +1,57 @@
 #![allow(dead_code)]
 
 // 表示用ライブラリ
-use embedded_graphics::{image::ImageRaw, pixelcolor::Gray8, prelude::*};
-use embedded_graphics_simulator::{SimulatorDisplay,Window, OutputSettingsBuilder};
+use embedded_graphics::{image::{Image, ImageRaw}, pixelcolor::{Gray8, Rgb888}, prelude::*};
+use embedded_graphics_simulator::{SimulatorDisplay, SimulatorEvent, Window, OutputSettingsBuilder};
 
 use crate::LCD_WIDTH;
 
 pub struct Lcd {
-    display: SimulatorDisplay<Gray8>,
+    display: SimulatorDisplay<Rgb888>,     // SGBの枠・色にも対応するためRGB
     window: Window,
 }
 
 impl Lcd {
-    pub fn new() -> Self{
+    // 通常は160x144、SGBの場合は256x224
+    pub fn new(width: u32, height: u32) -> Self{
         let output_settings = OutputSettingsBuilder::new().build();
         Self {
-            display: SimulatorDisplay::new(Size::new(160,144)),
+            display: SimulatorDisplay::new(Size::new(width, height)),
             window: Window::new("Debug", &output_settings),
         }
     }
 
     // 画面描画
     pub fn draw(&mut self, pixcles: &Vec<u8>) {
+        self.draw_at(pixcles, 0);
+    }
+
+    // 横にずらして描画（2台を並べる場合）
+    pub fn draw_at(&mut self, pixcles: &[u8], x: i32) {
         let raw = ImageRaw::<Gray8>::new(pixcles, LCD_WIDTH as u32);
         //let image = Image::new(&data, Point::zero());
-        let _ = raw.draw(&mut self.display);
+        let _ = Image::new(&raw, Point::new(x, 0)).draw(&mut self.display.color_converted());
         
     }
 
+    // RGB888の画面描画（SGB）
+    pub fn draw_rgb(&mut self, pixels: &[u8], width: u32) {
+        self.draw_rgb_at(pixels, width, 0);
+    }
+
+    // RGB888の画面を横にずらして描画（CGB）
+    pub fn draw_rgb_at(&mut self, pixels: &[u8], width: u32, x: i32) {
+        let raw = ImageRaw::<Rgb888>::new(pixels, width);
+        let _ = Image::new(&raw, Point::new(x, 0)).draw(&mut self.display);
+    }
+
     // 画面更新
     pub fn updata(&mut self) {
         self.window.update(&self.display);
     }
+
+    // ウィンドウのイベント（キー入力・終了）
+    pub fn events(&mut self) -> Vec<SimulatorEvent> {
+        self.window.events().collect()
+    }
 }
\ No newline at end of file
@@ -4,8 +4,9 @@
 
 use std::{
     env,
-    fs::File,
-    io::Read,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
     process::exit,
 };
 
@@ -15,19 +16,53 @@ mod ppu;
 mod lcd;
 mod mbc;
 mod hram;
+mod timer;
+mod apu;
+mod joypad;
+mod sgb;
+mod serial;
+mod infrared;
+mod wram;
+mod cheat;
+mod search;
+mod image;
+mod camera;
+mod save;
+mod info;
+mod licensee;
+mod inflate;
+mod archive;
+mod patch;
 mod bootrom;
+mod model;
 mod cartridge;
 mod registers;
 mod peripherals;
+mod console;
+mod state;
+mod rewind;
+mod movie;
+mod pacer;
 
 
+use embedded_graphics_simulator::{sdl2::{Keycode, Mod}, SimulatorEvent};
+
 use crate::{
-    cpu::Cpu,
+    cheat::Cheats,
+    console::Console,
+    infrared::Loopback,
     lcd::Lcd,
     //mbc::Mbc,
     bootrom::Bootrom,
     cartridge::Cartridge,
-    peripherals::Peripherals,
+    model::Model,
+    movie::Movie,
+    pacer::Pacer,
+    rewind::Rewind,
+    search::{Filter, RamSearch, Region, ValueType},
+    save::SaveFile,
+    serial::{cable, capture::{Capture, TestResult}, link::Link, printer::Printer},
+    sgb::{SGB_HEIGHT, SGB_WIDTH},
 };
 
 
@@ -37,51 +72,695 @@ pub const LCD_PIXELS: usize = LCD_WIDTH * LCD_HEIGHT;
 
 const CPU_CLOCK_HZ: u128 = 4_194_304;
 const M_CYCLE_CLOCK: u128 = 4;
-const M_CYCLE_NANOS: u128 = M_CYCLE_CLOCK * 1_000_000_000 / CPU_CLOCK_HZ;
+
+const SAVE_INTERVAL_FRAMES: u64 = 60 * 5;     // SRAMを定期保存する間隔（約5秒）
 
 
 fn main() {
     // 起動パラメータ確認
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let mut rom_path = None;
+    let mut camera_source = None;
+    let mut verify_checksum = true;
+    let mut zip_entry = None;
+    let mut patch_path = None;
+    let mut cheat_codes = Vec::new();
+    let mut bootrom_path = None;
+    let mut any_bootrom = false;
+    let mut skip_boot = false;
+    let mut test_rom = false;
+    let mut link = None;
+    let mut link_rom = None;
+    let mut printer_dir = None;
+    let mut printer_ext = "png";
+    let mut ir_loopback = false;
+    let mut model = None;
+    let mut rewind_interval = rewind::DEFAULT_INTERVAL;
+    let mut rewind_budget = rewind::DEFAULT_BUDGET;
+    let mut record_path = None;
+    let mut record_from = None;
+    let mut play_path = None;
+    let mut headless = false;
+    let mut max_frames = None;
+    let mut screenshot_path = None;
+    let mut dump_every = None;
+    let mut photo_dir = None;
+    let mut search_type = None;
+    let mut fast_forward = 4;
+    let mut slow_motion = 2;
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "info") {
+        args.next();
+        exit(info::run(args));
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--camera"      => camera_source = args.next(), // 画像ファイルかテストパターン名
+            "--no-checksum" => verify_checksum = false,     // 自作ROM用
+            "--zip-entry"   => zip_entry = args.next(),     // zip内のROMのファイル名
+            "--patch"       => patch_path = args.next().map(PathBuf::from),
+            "--bootrom"     => bootrom_path = args.next(),  // DMG・MGB・SGB・CGBのブートROMイメージ
+            "--any-bootrom" => any_bootrom = true,          // 既知のイメージ以外のブートROMも使う（改造・不良ダンプ用）
+            "--listen"      => link = args.next().map(|addr| (true, addr)),     // 通信ケーブル（host:port か unix:パス）
+            "--connect"     => link = args.next().map(|addr| (false, addr)),
+            "--local-link"  => link_rom = args.next(),      // 2Pのゲーム、同じウィンドウで2台を通信ケーブルでつなぐ
+            "--printer"     => printer_dir = args.next().map(PathBuf::from),    // ポケットプリンタの出力先
+            "--export-photos"   => photo_dir = args.next().map(PathBuf::from),  // 終了時にポケットカメラの写真を書き出す
+            "--printer-bmp" => printer_ext = "bmp",         // 印刷結果をPNGではなくBMPで保存する
+            "--ir-loopback" => ir_loopback = true,          // 赤外線LEDの光を自分で受ける（テスト用）
+            "--test-rom"    => test_rom = true,             // シリアル出力で結果を判定する
+            "--skip-boot"   => skip_boot = true,            // ブートROMを実行せず 0x0100 から始める
+            "--model"       => match args.next().as_deref().and_then(Model::from_name) {
+                Some(m) => model = Some(m),
+                None    => {
+                    eprintln!("--model must be one of dmg0, dmg, mgb, sgb, sgb2, cgb, agb");
+                    exit(1);
+                },
+            },
+            "--cheat"       => cheat_codes.extend(args.next()),     // 複数指定可
+            "--ram-search"  => match args.next().as_deref().and_then(ValueType::from_name) {    // F10～F12でRAM検索
+                Some(t) => search_type = Some(t),
+                None    => {
+                    eprintln!("--ram-search must be one of u8, u16le, u16be, bcd8, bcd16le, bcd16be");
+                    exit(1);
+                },
+            },
+            "--rewind-interval" => rewind_interval = parse_number(&arg, args.next()),          // 巻き戻し用に保存する間隔（フレーム）
+            "--rewind-budget"   => rewind_budget = parse_number(&arg, args.next()) << 20,      // 巻き戻しに使うメモリ（MB）
+            "--record"      => record_path = args.next().map(PathBuf::from),    // 入力をムービーに記録する
+            "--record-from" => record_from = Some(parse_number(&arg, args.next())), // ムービーをステートのスロットから始める
+            "--play"        => play_path = args.next().map(PathBuf::from),      // ムービーを再生する
+            "--headless"    => headless = true,             // ウィンドウを作らない
+            "--frames"      => max_frames = Some(parse_number(&arg, args.next()) as u64),   // 指定フレーム数で終了する
+            "--screenshot"  => screenshot_path = args.next().map(PathBuf::from),    // 終了時の画面を保存する
+            "--fast-forward"    => fast_forward = parse_number(&arg, args.next()),     // 早送りの倍率（0なら待たない）
+            "--slow-motion"     => slow_motion = parse_number(&arg, args.next()).max(1),   // スローの速さ（1/N）
+            "--dump-every"  => dump_every = Some(parse_number(&arg, args.next()).max(1) as u64),  // 指定フレーム毎に画面を保存する
+            _               => rom_path = Some(arg),
+        }
+    }
+    let Some(rom_path) = rom_path else {
         eprintln!("The file name argument is required.");
         exit(1);
+    };
+
+    // ムービーは機種・ブートの設定とRTCの時刻を合わせてからカートリッジを作る
+    if record_path.is_some() && play_path.is_some() {
+        eprintln!("--record and --play cannot be used together");
+        exit(1);
     }
+    if (record_path.is_some() || play_path.is_some()) && (link.is_some() || link_rom.is_some()) {
+        eprintln!("Movies cannot be used with a link cable");
+        exit(1);
+    }
+    let mut movie = play_path.as_ref().map(|path| match Movie::load(path) {
+        Ok(movie) => movie,
+        Err(e)    => {
+            eprintln!("Cannot load movie {}: {}", path.display(), e);
+            exit(1);
+        },
+    });
+    if let Some(ref movie) = movie {
+        if camera_source.is_some() && camera_source.as_deref() != movie.camera() {
+            eprintln!("The movie was recorded with camera source {}", movie.camera().unwrap_or("none"));
+            exit(1);
+        }
+        model = Some(movie.model());
+        skip_boot = movie.skip_boot();
+        camera_source = movie.camera().map(str::to_string);
+    }
+    let movie_mode = record_path.is_some() || play_path.is_some();
 
-    // カードリッジ読み込み
-    fn file2vec(fname: &String) -> Vec<u8> {
-        if let Ok(mut file) = File::open(fname) {
-          let mut ret = vec![];
-          file.read_to_end(&mut ret).unwrap();
-          ret
-        } else {
-          panic!("Cannot open {}.", fname);
+    let (mut cartridge, base_path) = load_cartridge(&rom_path, zip_entry.as_deref(), patch_path, verify_checksum);
+    if let Some(ref name) = camera_source {
+        match camera::open_source(name) {
+            Ok(source) => cartridge.set_camera_source(source),
+            Err(e)     => eprintln!("Cannot open camera image {}: {}", name, e),
+        }
+    }
+
+    // チートコード、ROMと同じ名前の .cht とコマンドラインの指定
+    // ムービーは切り替えを記録しないため、記録・再生中はチートを使わない
+    let cheat_path = base_path.with_extension("cht");
+    if movie_mode {
+        if !cheat_codes.is_empty() {
+            eprintln!("Cheats cannot be used with a movie");
+            exit(1);
+        }
+        if cheat_path.exists() {
+            eprintln!("Warning: {} is not loaded while recording or playing a movie", cheat_path.display());
+        }
+    } else if cheat_path.exists() {
+        match Cheats::load(&cheat_path) {
+            Ok(cheats) => cartridge.cheats = cheats,
+            Err(e)     => eprintln!("Cannot load cheat file {}: {}", cheat_path.display(), e),
+        }
+    }
+    for code in cheat_codes {
+        if let Err(e) = cartridge.cheats.add(&code, "") {
+            eprintln!("Invalid cheat code {}: {}", code, e);
         }
     }
-    let cartridge_raw = file2vec(&args[1]);
-    let cartridge = Cartridge::new(cartridge_raw);
 
     // エミュレータ作成
-    let mut lcd = Lcd::new();
-    let mut cpu = Cpu::new();
-    let bootrom = Bootrom::new();
-    let mut peripherals = Peripherals::new(bootrom, cartridge);
-
-    //let time = time::Instant::now;
-    //let mut elapsed = 0;
-    let is_lcd = true;
+    // ムービーは .sav を読み書きしない（電源投入時のSRAMを揃えるため）
+    let mut save_files = vec![if movie_mode { None } else { open_save_file(&mut cartridge, &base_path) }];
+    let mut base_paths = vec![base_path.clone()];
+    let mut consoles = vec![create_console(cartridge, bootrom_path.as_deref(), any_bootrom, model, skip_boot)];
+
+    // 2台目、同じROMの場合は .sav が重なるため2台目は保存しない
+    if let Some(link_rom) = link_rom {
+        let (mut cartridge, link_base_path) = load_cartridge(&link_rom, None, None, verify_checksum);
+        if link_base_path == base_path {
+            eprintln!("Warning: player 2 uses the same rom, its save data is not written");
+            save_files.push(None);
+        } else {
+            save_files.push(open_save_file(&mut cartridge, &link_base_path));
+        }
+        base_paths.push(link_base_path);
+        consoles.push(create_console(cartridge, bootrom_path.as_deref(), any_bootrom, model, skip_boot));
+        let (cable1, cable2) = cable::pair();
+        consoles[0].peripherals.serial.connect(Box::new(cable1));
+        consoles[1].peripherals.serial.connect(Box::new(cable2));
+        // 赤外線も向かい合わせる
+        let (ir1, ir2) = infrared::pair();
+        consoles[0].peripherals.infrared.connect(Box::new(ir1));
+        consoles[1].peripherals.infrared.connect(Box::new(ir2));
+    }
+
+    // 通信ケーブル
+    let networked = link.is_some();
+    if let Some((listen, addr)) = link {
+        let link = if listen { Link::listen(&addr) } else { Link::connect(&addr) };
+        match link {
+            Ok(link) => consoles[0].peripherals.serial.connect(Box::new(link)),
+            Err(e)   => {
+                eprintln!("Cannot open link cable {}: {}", addr, e);
+                exit(1);
+            },
+        }
+    }
+
+    if ir_loopback {
+        consoles[0].peripherals.infrared.connect(Box::new(Loopback::default()));
+    }
+
+    if photo_dir.is_some() && !consoles[0].peripherals.cartridge.has_camera() {
+        eprintln!("--export-photos requires a Pocket Camera cartridge");
+        exit(1);
+    }
+
+    // ポケットプリンタ
+    if let Some(dir) = printer_dir {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            eprintln!("Cannot create printer directory {}: {}", dir.display(), e);
+            exit(1);
+        }
+        consoles[0].peripherals.serial.connect(Box::new(Printer::new(dir, printer_ext)));
+    }
+
+    // テストROMはシリアル出力を記録して結果で終了する
+    let capture = test_rom.then(|| {
+        let (capture, output) = Capture::new(true);
+        consoles[0].peripherals.serial.connect(Box::new(capture));
+        output
+    });
+    let sgb = consoles.len() == 1 && consoles[0].peripherals.sgb.is_some();
+    let mut lcd = (!headless).then(|| if sgb {
+        Lcd::new(SGB_WIDTH as u32, SGB_HEIGHT as u32)
+    } else {
+        Lcd::new((LCD_WIDTH * consoles.len()) as u32, LCD_HEIGHT as u32)
+    });
+
+    // ムービーの開始
+    if let Some(ref path) = record_path {
+        let state = record_from.map(|slot| {
+            let state_path = state::slot_path(&base_path, slot);
+            fs::read(&state_path).unwrap_or_else(|e| {
+                eprintln!("Cannot read state {}: {}", state_path.display(), e);
+                exit(1);
+            })
+        });
+        movie = Some(Movie::new(&consoles[0], skip_boot, camera_source.clone(), state));
+        println!("Recording movie {}", path.display());
+    }
+    if let Some(ref mut movie) = movie {
+        if !movie.matches_rom(consoles[0].peripherals.cartridge.rom()) {
+            eprintln!("The movie was recorded with a different rom");
+            exit(1);
+        }
+        // ブートROMを飛ばす場合はイメージの違いは結果に影響しない
+        if !movie.skip_boot() && !movie.matches_bootrom(&consoles[0]) {
+            eprintln!("The movie was recorded with a different boot rom");
+            exit(1);
+        }
+        if let Err(e) = movie.begin(&mut consoles[0]) {
+            eprintln!("Cannot start movie: {}", e);
+            exit(1);
+        }
+    }
+    let mut playing = play_path.is_some();
+    let mut held = 0;       // キーボードで押されているボタン
+    let mut ram_search: Option<RamSearch> = None;  // --ram-search の検索中の候補
+    if let Some(buttons) = movie.as_ref().filter(|_| playing).and_then(|movie| movie.input(&consoles[0])) {
+        consoles[0].set_buttons(buttons);
+    }
+
+    // 巻き戻しは1台で通信ケーブルを使わない場合のみ
+    let mut rewind = (consoles.len() == 1 && !networked && !headless)
+        .then(|| Rewind::new(rewind_interval, rewind_budget));
+    let mut rewinding = false;
+
+    // 画面が無ければ実時間に合わせない
+    let mut pacer = (!headless).then(Pacer::new);
+    let mut fast_forwarding = false;
+    let mut slow = false;
+    let mut frames: u64 = 0;
+    let mut player = 0;     // 入力先
+    let mut exit_code = None;   // テストROMの結果
     loop {
-        cpu.emulate_cycle(&mut peripherals); 
+        // 全ての本体を1Mサイクルずつ進める
+        let frame = if let Some(rewind) = rewind.as_mut().filter(|_| rewinding) {
+            // 巻き戻し中は1フレームずつ戻す
+            rewind.step_back(&mut consoles[0]);
+            true
+        } else {
+            let frame = console::emulate_cycle_linked(&mut consoles);
+
+            // テストROMの結果（画面が無効でも判定する）
+            if let Some(result) = capture.as_ref().and_then(|output| output.result()) {
+                println!();
+                exit_code = Some(if result == TestResult::Passed { 0 } else { 1 });
+                break;
+            }
+            frame
+        };
+        if !frame {
+            continue;
+        }
+        if let Some(rewind) = rewind.as_mut().filter(|_| !rewinding) {
+            rewind.end_frame(&consoles[0]);
+        }
+        if let Some(ref mut movie) = movie {
+            if !playing && !rewinding {
+                movie.record_frame(&consoles[0]);
+            }
+            movie.sync_clock(&mut consoles[0]);
+        }
+
+        // 画面表示
+        if let Some(ref mut lcd) = lcd {
+            if sgb {
+                let peripherals = &consoles[0].peripherals;
+                if let Some(ref sgb) = peripherals.sgb {
+                    lcd.draw_rgb(&sgb.render(&peripherals.ppu.shades), SGB_WIDTH as u32);
+                }
+            } else {
+                for (i, console) in consoles.iter().enumerate() {
+                    let ppu = &console.peripherals.ppu;
+                    match ppu.rgb() {
+                        Some(rgb) => lcd.draw_rgb_at(&rgb, LCD_WIDTH as u32, (i * LCD_WIDTH) as i32),
+                        None      => lcd.draw_at(&ppu.buffer, (i * LCD_WIDTH) as i32),
+                    }
+                }
+            }
+            lcd.updata();
+            let mut quit = false;
+            for event in lcd.events() {
+                match event {
+                    SimulatorEvent::Quit => quit = true,
+                    SimulatorEvent::KeyDown { keycode, keymod, repeat: false } => {
+                        if let Some(slot) = state_slot(keycode) {
+                            // F1～F9で読み込み、Shiftを押しながらで保存
+                            let path = state::slot_path(&base_paths[player], slot);
+                            if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                match state::save_slot(&consoles[player], &path) {
+                                    Ok(())  => println!("Saved state {}", path.display()),
+                                    Err(e)  => eprintln!("Cannot save state {}: {}", path.display(), e),
+                                }
+                            } else {
+                                match state::load_slot(&mut consoles[player], &path) {
+                                    Ok(())  => println!("Loaded state {}", path.display()),
+                                    Err(e)  => eprintln!("Cannot load state {}: {}", path.display(), e),
+                                }
+                                if let Some(ref mut movie) = movie {
+                                    if record_path.is_some() {
+                                        movie.rerecord();
+                                    }
+                                    movie.sync_clock(&mut consoles[player]);
+                                }
+                            }
+                        } else if let Some(button) = joypad_button(keycode) {
+                            held |= button;
+                        } else if keycode == Keycode::Space {
+                            // スペースキーを押している間は早送り
+                            fast_forwarding = true;
+                        } else if keycode == Keycode::S {
+                            // Sキーでスローの切り替え
+                            slow = !slow;
+                            println!("Slow motion: {}", if slow { "on" } else { "off" });
+                        } else if keycode == Keycode::R && rewind.is_some() {
+                            // Rキーを押している間は巻き戻す
+                            rewinding = true;
+                        } else if keycode == Keycode::Tab && consoles.len() > 1 {
+                            // Tabキーで入力先を切り替える
+                            consoles[player].release(0xFF);
+                            held = 0;
+                            player = (player + 1) % consoles.len();
+                            println!("Input: player {}", player + 1);
+                        } else if let Some(index) = cheat_index(keycode) {
+                            // 1～9キーでチートの有効・無効を切り替える
+                            let cheats = &mut consoles[0].peripherals.cartridge.cheats;
+                            if let Some(enabled) = cheats.toggle(index) {
+                                let code = &cheats.codes()[index];
+                                println!("Cheat {} {} {}: {}", index + 1, code.code, code.name, if enabled { "on" } else { "off" });
+                            }
+                        } else if let Some(value_type) = search_type.filter(|_| matches!(keycode, Keycode::F10 | Keycode::F11 | Keycode::F12)) {
+                            // F10で検索開始（Shiftで値を指定して絞り込み）、F11で変化あり（Shiftで変化なし）、F12で増加（Shiftで減少）
+                            let console = &consoles[player];
+                            let (bus, interrupts) = (&console.peripherals, &console.cpu.interrupts);
+                            let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                            match (keycode, ram_search.as_mut()) {
+                                (Keycode::F10, Some(search)) if shift => {
+                                    if let Some(val) = read_search_value() {
+                                        search.filter(bus, interrupts, Filter::Value(val));
+                                    }
+                                },
+                                (Keycode::F10, _) => ram_search = Some(RamSearch::new(bus, interrupts, &Region::ALL, value_type)),
+                                (Keycode::F11, Some(search)) => {
+                                    search.filter(bus, interrupts, if shift { Filter::Equal } else { Filter::Changed });
+                                },
+                                (_, Some(search)) => {
+                                    search.filter(bus, interrupts, if shift { Filter::Decreased } else { Filter::Increased });
+                                },
+                                (_, None) => println!("Press F10 to start a RAM search"),
+                            }
+                            if let Some(ref search) = ram_search {
+                                print_search(search);
+                            }
+                        }
+                    },
+                    SimulatorEvent::KeyUp { keycode, .. } => {
+                        if let Some(button) = joypad_button(keycode) {
+                            held &= !button;
+                        } else if keycode == Keycode::Space {
+                            fast_forwarding = false;
+                        } else if keycode == Keycode::R && rewinding {
+                            rewinding = false;
+                            if let Some(ref mut movie) = movie.as_mut().filter(|_| record_path.is_some()) {
+                                movie.rerecord();
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            if quit {
+                break;
+            }
+        }
+
+        // ボタンはフレームの区切りでまとめて反映する（ムービーの再生と同じ入力になるように）
+        // 巻き戻し中は記録された入力が再生される
+        if playing {
+            match movie.as_ref().and_then(|movie| movie.input(&consoles[0])) {
+                Some(buttons) => consoles[0].set_buttons(buttons),
+                None          => {
+                    println!("Movie finished");
+                    playing = false;
+                    // 画面が無く終了フレームの指定も無ければ、再生の終わりで終了する
+                    if headless && max_frames.is_none() {
+                        break;
+                    }
+                },
+            }
+        }
+        if !playing && !rewinding {
+            consoles[player].set_buttons(held);
+        }
+
+        // 実時間に合わせて待つ
+        if let Some(ref mut pacer) = pacer {
+            pacer.set_speed(if fast_forwarding {
+                fast_forward as f64
+            } else if slow {
+                1.0 / slow_motion as f64
+            } else {
+                1.0
+            });
+            pacer.end_frame();
+        }
+
+        // 画面の保存
+        frames += 1;
+        if dump_every.is_some_and(|k| frames.is_multiple_of(k)) {
+            let path = dump_path(screenshot_path.as_deref(), frames);
+            if let Err(e) = save_screen(&path, &consoles, sgb) {
+                eprintln!("Cannot write screenshot {}: {}", path.display(), e);
+            }
+        }
+        if max_frames.is_some_and(|n| frames >= n) {
+            break;
+        }
+
+        // SRAMの定期保存
+        if frames.is_multiple_of(SAVE_INTERVAL_FRAMES) {
+            for (console, save_file) in consoles.iter().zip(save_files.iter_mut()) {
+                if let Some(ref mut save_file) = save_file {
+                    if let Err(e) = save_file.flush(&console.peripherals.cartridge) {
+                        eprintln!("Cannot write save file: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    // 終了時に保存
+    if let Some(path) = screenshot_path {
+        match save_screen(&path, &consoles, sgb) {
+            Ok(())  => println!("Saved screenshot {}", path.display()),
+            Err(e)  => eprintln!("Cannot write screenshot {}: {}", path.display(), e),
+        }
+    }
+    if let (Some(path), Some(movie)) = (record_path, movie.as_ref()) {
+        match movie.save(&path) {
+            Ok(())  => println!("Saved movie {} ({} frames, {} rerecords)", path.display(), movie.len(), movie.rerecords()),
+            Err(e)  => eprintln!("Cannot write movie {}: {}", path.display(), e),
+        }
+    }
+    if let Some(dir) = photo_dir {
+        match fs::create_dir_all(&dir).and_then(|()| consoles[0].peripherals.cartridge.export_camera_photos(&dir)) {
+            Ok(count)   => println!("Saved {} photos to {}", count, dir.display()),
+            Err(e)      => eprintln!("Cannot write photos to {}: {}", dir.display(), e),
+        }
+    }
+    for (console, save_file) in consoles.iter().zip(save_files.iter_mut()) {
+        if let Some(ref mut save_file) = save_file {
+            if let Err(e) = save_file.save(&console.peripherals.cartridge) {
+                eprintln!("Cannot write save file: {}", e);
+            }
+        }
+    }
+    if let Some(code) = exit_code {
+        exit(code);
+    }
+}
 
-        if peripherals.ppu.emulate_cycle() {
-            // 画面表示
-            if is_lcd {
-                lcd.draw(&peripherals.ppu.buffer);
-                lcd.updata();
+// カードリッジ読み込み、.zip・.gz は展開する
+// 戻り値のパスは .gz を除いたもの（.sav などの名前に使う）
+fn load_cartridge(rom_path: &str, zip_entry: Option<&str>, patch_path: Option<PathBuf>, verify_checksum: bool) -> (Cartridge, PathBuf) {
+    let cartridge_raw = match archive::load_rom(Path::new(rom_path), zip_entry) {
+        Ok(rom) => rom,
+        Err(e)  => {
+            eprintln!("Cannot open {}: {}", rom_path, e);
+            exit(1);
+        },
+    };
+
+    // パッチの適用、指定が無ければROMと同じ名前のパッチを探す
+    let base_path = archive::base_path(Path::new(rom_path));
+    let cartridge_raw = match patch_path.or_else(|| patch::find_patch(&base_path)) {
+        Some(path) => {
+            let patched = fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| patch::apply(&cartridge_raw, &data).map_err(|e| e.to_string()));
+            match patched {
+                Ok(rom) => rom,
+                Err(e)  => {
+                    eprintln!("Cannot apply patch {}: {}", path.display(), e);
+                    exit(1);
+                },
             }
+        },
+        None => cartridge_raw,
+    };
+    match Cartridge::from_bytes(cartridge_raw, verify_checksum) {
+        Ok(cartridge) => (cartridge, base_path),
+        Err(e) => {
+            eprintln!("Cannot load {}: {}", rom_path, e);
+            exit(1);
+        },
+    }
+}
+
+// バッテリーバックアップがあれば .sav を読み込む
+fn open_save_file(cartridge: &mut Cartridge, base_path: &Path) -> Option<SaveFile> {
+    let mut save_file = cartridge.has_battery().then(|| SaveFile::new(base_path));
+    if let Some(ref mut save_file) = save_file {
+        if let Err(e) = save_file.load(cartridge) {
+            eprintln!("Cannot load save file: {}", e);
         }
     }
+    save_file
+}
 
+// 本体の作成
+fn create_console(cartridge: Cartridge, bootrom_path: Option<&str>, any_bootrom: bool, model: Option<Model>, skip_boot: bool) -> Console {
+    let bootrom = bootrom_path.and_then(|path| load_bootrom(path, any_bootrom));
+    // 機種の指定が無ければブートROM、ヘッダの順に決める
+    let model = model
+        .or_else(|| bootrom.as_ref().and_then(Bootrom::model))
+        .unwrap_or_else(|| Model::detect(&cartridge.header));
+    // 機種に合わないサイズのブートROMは使わない
+    let bootrom = match bootrom {
+        Some(bootrom) => match bootrom.check_model(model) {
+            Ok(()) => bootrom,
+            Err(e) => {
+                eprintln!("Cannot use boot rom {}: {}, using the built-in one", bootrom_path.unwrap_or_default(), e);
+                Bootrom::new()
+            },
+        },
+        None => Bootrom::new(),
+    };
+    if let Some(bootrom_model) = bootrom.model().filter(|&m| m != model) {
+        eprintln!("Warning: {} boot rom on {} model", bootrom_model.name(), model.name());
+    }
+    let mut console = Console::new(model, bootrom, cartridge);
+    if skip_boot {
+        console.skip_boot();
+    }
+    console
+}
+
+// ブートROMの読み込み、読めない場合や未知のイメージ（any_bootrom でなければ）は None で内蔵のものを使う
+fn load_bootrom(path: &str, any_bootrom: bool) -> Option<Bootrom> {
+    let bootrom = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| Bootrom::from_bytes(data, any_bootrom).map_err(|e| e.to_string()));
+    match bootrom {
+        Ok(bootrom) => {
+            if bootrom.name().is_none() {
+                eprintln!("Warning: {} is not a known boot rom image", path);
+            }
+            Some(bootrom)
+        },
+        Err(e) => {
+            eprintln!("Cannot load boot rom {}: {}, using the built-in one", path, e);
+            None
+        },
+    }
+}
 
-   
-}
\ No newline at end of file
+// 画面を画像として保存する
+// SGBは枠付きのRGB、それ以外は全ての本体を横に並べる（CGBを含む場合はRGB、それ以外はグレースケール）
+fn save_screen(path: &Path, consoles: &[Console], sgb: bool) -> io::Result<()> {
+    if sgb {
+        let peripherals = &consoles[0].peripherals;
+        if let Some(ref sgb) = peripherals.sgb {
+            return image::save_rgb(path, SGB_WIDTH, SGB_HEIGHT, &sgb.render(&peripherals.ppu.shades));
+        }
+    }
+    let width = LCD_WIDTH * consoles.len();
+    if consoles.iter().any(|console| console.peripherals.ppu.rgb().is_some()) {
+        let mut pixels = vec![0; width * LCD_HEIGHT * 3];
+        for (i, console) in consoles.iter().enumerate() {
+            let ppu = &console.peripherals.ppu;
+            let rgb = ppu.rgb().unwrap_or_else(|| ppu.buffer.iter().flat_map(|&v| [v; 3]).collect());
+            for (y, row) in rgb.chunks(LCD_WIDTH * 3).enumerate() {
+                pixels[(y * width + i * LCD_WIDTH) * 3..][..LCD_WIDTH * 3].copy_from_slice(row);
+            }
+        }
+        return image::save_rgb(path, width, LCD_HEIGHT, &pixels);
+    }
+    let mut pixels = vec![0; width * LCD_HEIGHT];
+    for (i, console) in consoles.iter().enumerate() {
+        for (y, row) in console.peripherals.ppu.buffer.chunks(LCD_WIDTH).enumerate() {
+            pixels[y * width + i * LCD_WIDTH..][..LCD_WIDTH].copy_from_slice(row);
+        }
+    }
+    image::save_gray(path, width, LCD_HEIGHT, &pixels)
+}
+
+// 定期保存する画面のファイル名、--screenshot の名前にフレーム番号を付ける（out.png なら out_000060.png）
+fn dump_path(screenshot_path: Option<&Path>, frame: u64) -> PathBuf {
+    let base = screenshot_path.unwrap_or(Path::new("frame.png"));
+    let stem = base.file_stem().map_or("frame".into(), |stem| stem.to_string_lossy());
+    let ext = base.extension().map_or("png".into(), |ext| ext.to_string_lossy());
+    base.with_file_name(format!("{}_{:06}.{}", stem, frame, ext))
+}
+
+// 数値の引数
+// RAM検索の値を端末から読む、0xで始まれば16進数
+fn read_search_value() -> Option<u32> {
+    print!("RAM search value: ");
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).ok()?;
+    let line = line.trim();
+    match line.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None      => line.parse().ok(),
+    }
+}
+
+// RAM検索の候補数、少なければアドレスと値も表示する
+fn print_search(search: &RamSearch) {
+    println!("RAM search: {} candidates", search.len());
+    if search.len() <= 10 {
+        for candidate in search.candidates() {
+            println!("  {:04X}: {}", candidate.addr, candidate.value);
+        }
+    }
+}
+
+fn parse_number(flag: &str, val: Option<String>) -> usize {
+    match val.as_deref().map(str::parse) {
+        Some(Ok(n)) => n,
+        _           => {
+            eprintln!("{} requires a number", flag);
+            exit(1);
+        },
+    }
+}
+
+// チート切り替えキーの番号
+fn cheat_index(keycode: Keycode) -> Option<usize> {
+    [
+        Keycode::Num1, Keycode::Num2, Keycode::Num3,
+        Keycode::Num4, Keycode::Num5, Keycode::Num6,
+        Keycode::Num7, Keycode::Num8, Keycode::Num9,
+    ].iter().position(|&key| key == keycode)
+}
+
+// ステートのスロット番号（F1～F9が1～9）
+fn state_slot(keycode: Keycode) -> Option<usize> {
+    [
+        Keycode::F1, Keycode::F2, Keycode::F3,
+        Keycode::F4, Keycode::F5, Keycode::F6,
+        Keycode::F7, Keycode::F8, Keycode::F9,
+    ].iter().position(|&key| key == keycode).map(|i| i + 1)
+}
+
+// キーとボタンの対応
+fn joypad_button(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Right     => Some(joypad::RIGHT),
+        Keycode::Left      => Some(joypad::LEFT),
+        Keycode::Up        => Some(joypad::UP),
+        Keycode::Down      => Some(joypad::DOWN),
+        Keycode::X         => Some(joypad::A),
+        Keycode::Z         => Some(joypad::B),
+        Keycode::Backspace => Some(joypad::SELECT),
+        Keycode::Return    => Some(joypad::START),
+        _                  => None,
+    }
+}
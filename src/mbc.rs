@@ -1,37 +1,306 @@
 // メインバンクコントローラ
 
+use std::io;
+
+use crate::{
+    camera::ImageSource,
+    cartridge::NINTENDO_LOGO,
+    mbc::{
+        camera::Camera,
+        eeprom::Eeprom,
+        flash::Flash,
+        huc3::HuC3Rtc,
+        rtc::Mbc3Rtc,
+    },
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+mod camera;
+mod eeprom;
+mod flash;
+mod huc3;
+mod rtc;
+
+pub use rtc::system_secs;
+
 pub enum Mbc {
-    NoMbc,
+    RomOnly,
     Mbc1 {
         sram_enable: bool,
         low_bank: usize,
         high_bank: usize,
         bank_mode: bool,
         rom_banks: usize,
+        multicart: bool,        // MBC1M（HIGHバンクが bit18 に配線されている）
+        sram_banked: bool,      // SRAMが8KBより大きい場合のみHIGHバンクでSRAMを切り替える
+    },
+    Mbc3 {
+        sram_enable: bool,      // SRAMとRTCの有効化
+        rom_bank: usize,
+        ram_bank: usize,        // 0x08-0x0C はRTCのレジスタ
+        rom_banks: usize,
+        rtc: Option<Mbc3Rtc>,
+    },
+    HuC1 {
+        ir_mode: bool,          // SRAM領域が赤外線ポートになっているか
+        ir_led: bool,
+        ir_light: bool,         // 受光しているか
+        rom_bank: usize,
+        ram_bank: usize,
+        rom_banks: usize,
+    },
+    HuC3 {
+        mode: u8,               // 0x0000-0x1FFF に書き込んだ値でSRAM領域の機能が決まる
+        ir_led: bool,
+        ir_light: bool,
+        rom_bank: usize,
+        ram_bank: usize,
+        rom_banks: usize,
+        rtc: HuC3Rtc,
+    },
+    Mmm01 {
+        sram_enable: bool,
+        locked: bool,           // 一度ロックするとマルチカートのメニューから抜ける
+        rom_bank: usize,        // bit0-4: バンク、bit5-8: 外側のバンク
+        rom_mask: usize,        // ロック後に書き換えられないバンクのビット
+        ram_bank: usize,
+        ram_mask: usize,
+        rom_banks: usize,
+    },
+    Tama5 {
+        reg_select: usize,      // 0xA001 で選択したレジスタ
+        regs: [u8; 16],         // 4bitのレジスタ
+        rom_banks: usize,
+    },
+    Mbc6 {
+        sram_enable: bool,
+        ram_banks: [usize; 2],  // 0xA000-0xAFFF, 0xB000-0xBFFF の4KBバンク
+        rom_banks: [usize; 2],  // 0x4000-0x5FFF, 0x6000-0x7FFF の8KBバンク
+        flash_select: [bool; 2],// バンクがフラッシュを指しているか
+        flash_enable: bool,
+        flash_write_enable: bool,
+        flash: Flash,
+        ram_size: usize,        // SRAMの後ろにフラッシュを配置する
+        rom_len: usize,
+    },
+    Mbc7 {
+        sram_enable: [bool; 2], // 0x0000 と 0x4000 の両方で有効化が必要
+        rom_bank: usize,
+        rom_banks: usize,
+        accel: (u16, u16),      // 現在の加速度センサーの値
+        latched: (u16, u16),    // ラッチされた値
+        eeprom: Eeprom,
+    },
+    PocketCamera {
+        sram_enable: bool,
+        rom_bank: usize,
+        ram_bank: usize,        // bit4が立っているとSRAM領域がカメラのレジスタになる
+        rom_banks: usize,
+        camera: Camera,
     },
 }
 
+// MBC7の加速度センサー、水平時の値と1Gあたりの変化量
+const ACCEL_CENTER: u16 = 0x81D0;
+const ACCEL_1G: f32 = 0x70 as f32;
+
+// MBC1Mの判定
+// マルチカートは 0x40000 毎に各ゲームのヘッダ（任天堂ロゴ）が並んでいる
+pub fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+    (1..4)
+        .map(|i| i * 0x40000 + 0x104)
+        .any(|addr| rom[addr..addr + NINTENDO_LOGO.len()] == NINTENDO_LOGO)
+}
+
 impl Mbc {
-    // 初期化
-    pub fn new(cartridge_type: u8, rom_banks: usize) -> Self {
-        match cartridge_type {
-            0x00 | 0x08 | 0x09 => Self::NoMbc,
+    // 初期化、未対応のカートリッジタイプはNone
+    pub fn new(cartridge_type: u8, rom_banks: usize, sram_size: usize, multicart: bool) -> Option<Self> {
+        Some(match cartridge_type {
+            0x00 | 0x08 | 0x09 => Self::RomOnly,
             0x01..=0x03        => Self::Mbc1 {
                 sram_enable: false,
                 low_bank: 0b00001,                          // LOWバンクレジスタは1で初期化が必要
                 high_bank: 0b00,                            // HIGHバンクレジスタは下位2bitのみ書き込み
                 bank_mode: false,
                 rom_banks,
+                multicart,
+                sram_banked: sram_size > 0x2000,
+            },
+            0x0B..=0x0D        => Self::Mmm01 {
+                sram_enable: false,
+                locked: false,
+                rom_bank: 0,
+                rom_mask: 0,
+                ram_bank: 0,
+                ram_mask: 0,
+                rom_banks,
+            },
+            0x0F..=0x13        => Self::Mbc3 {
+                sram_enable: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                rom_banks,
+                rtc: if cartridge_type <= 0x10 { Some(Mbc3Rtc::new()) } else { None },
+            },
+            0x20               => Self::Mbc6 {
+                sram_enable: false,
+                ram_banks: [0, 0],
+                rom_banks: [0, 0],
+                flash_select: [false, false],
+                flash_enable: false,
+                flash_write_enable: false,
+                flash: Flash::new(),
+                ram_size: sram_size,
+                rom_len: rom_banks << 14,
+            },
+            0x22               => Self::Mbc7 {
+                sram_enable: [false, false],
+                rom_bank: 1,
+                rom_banks,
+                accel: (ACCEL_CENTER, ACCEL_CENTER),
+                latched: (0x8000, 0x8000),
+                eeprom: Eeprom::new(),
             },
-            _                  => panic!("Not supported"),
+            0xFC               => Self::PocketCamera {
+                sram_enable: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                rom_banks,
+                camera: Camera::new(),
+            },
+            0xFD               => Self::Tama5 {
+                reg_select: 0,
+                regs: [0; 16],
+                rom_banks,
+            },
+            0xFE               => Self::HuC3 {
+                mode: 0,
+                ir_led: false,
+                ir_light: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                rom_banks,
+                rtc: HuC3Rtc::new(),
+            },
+            0xFF               => Self::HuC1 {
+                ir_mode: false,
+                ir_led: false,
+                ir_light: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                rom_banks,
+            },
+            _                  => return None,
+        })
+    }
+
+    // MBCの名前
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Self::RomOnly => "No Mbc",
+            Self::Mbc1 { multicart: true, .. } => "MBC1M",
+            Self::Mbc1 { .. } => "MBC1",
+            Self::Mbc3 { rtc: Some(_), .. } => "MBC3+RTC",
+            Self::Mbc3 { .. } => "MBC3",
+            Self::HuC1 { .. } => "HuC1",
+            Self::HuC3 { .. } => "HuC3",
+            Self::Mmm01 { .. } => "MMM01",
+            Self::Tama5 { .. } => "TAMA5",
+            Self::Mbc6 { .. } => "MBC6",
+            Self::Mbc7 { .. } => "MBC7",
+            Self::PocketCamera { .. } => "POCKET CAMERA",
+        }
+    }
+
+    // カートリッジに必要なSRAMのサイズ
+    // ヘッダに記載されない内蔵メモリはSRAMの一部として確保する
+    pub fn sram_size(&self, header_sram_size: usize) -> usize {
+        match *self {
+            Self::Tama5 { .. } => 0x20,                     // 32byte
+            Self::Mbc6 { .. }  => header_sram_size + flash::FLASH_SIZE,
+            Self::Mbc7 { .. }  => 0x100,                    // 93LC56（256byte）
+            Self::PocketCamera { .. } => 0x20000,           // 128KB、撮影結果の書き込み先
+            _                  => header_sram_size,
+        }
+    }
+
+    // MBC7の加速度センサーの値を設定する、単位はG
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        if let Self::Mbc7 { ref mut accel, .. } = *self {
+            let raw = |g: f32| (ACCEL_CENTER as f32 + g * ACCEL_1G).clamp(0.0, u16::MAX as f32) as u16;
+            *accel = (raw(x), raw(y));
+        }
+    }
+
+    // .savの末尾に付けるRTCのデータ
+    pub fn rtc_trailer(&self) -> Option<Vec<u8>> {
+        match *self {
+            Self::Mbc3 { rtc: Some(ref rtc), .. } => Some(rtc.to_trailer()),
+            _ => None,
+        }
+    }
+
+    // .savの末尾のRTCのデータを読み込む
+    pub fn load_rtc_trailer(&mut self, data: &[u8]) -> bool {
+        match *self {
+            Self::Mbc3 { rtc: Some(ref mut rtc), .. } => rtc.load_trailer(data),
+            _ => false,
+        }
+    }
+
+    // ポケットカメラに入力する画像を設定する
+    pub fn has_camera(&self) -> bool {
+        matches!(*self, Self::PocketCamera { .. })
+    }
+
+    // RTCの時刻を実時間の代わりに指定の時刻にする（ムービー用）
+    pub fn set_clock(&mut self, secs: u64) {
+        match *self {
+            Self::Mbc3 { rtc: Some(ref mut rtc), .. } => rtc.set_clock(secs),
+            Self::HuC3 { ref mut rtc, .. } => rtc.set_clock(secs),
+            _ => (),
+        }
+    }
+
+    // RTCを指定の時刻に電源を入れた状態にする
+    pub fn start_clock(&mut self, secs: u64) {
+        match *self {
+            Self::Mbc3 { rtc: Some(ref mut rtc), .. } => rtc.start_clock(secs),
+            Self::HuC3 { ref mut rtc, .. } => rtc.start_clock(secs),
+            _ => (),
+        }
+    }
+
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        if let Self::PocketCamera { ref mut camera, .. } = *self {
+            camera.set_source(source);
+        }
+    }
+
+    // 赤外線LEDの状態、赤外線ポートの無いカートリッジは None
+    pub fn ir_led(&self) -> Option<bool> {
+        match *self {
+            Self::HuC1 { ir_led, .. } | Self::HuC3 { ir_led, .. } => Some(ir_led),
+            _ => None,
+        }
+    }
+
+    // 受光状態の設定
+    pub fn set_ir_light(&mut self, light: bool) {
+        match *self {
+            Self::HuC1 { ref mut ir_light, .. } | Self::HuC3 { ref mut ir_light, .. } => *ir_light = light,
+            _ => (),
         }
     }
 
     // 書き込み
-    pub fn write(&mut self, addr: u16, val: u8) {
+    pub fn write(&mut self, sram: &mut [u8], addr: u16, val: u8) {
         // 列挙型に直接アクセスするとエラーになる
         match *self {
-            Self::NoMbc => {},
+            Self::RomOnly => {},
             Self::Mbc1 {
                 ref mut sram_enable,
                 ref mut low_bank,
@@ -49,42 +318,854 @@ impl Mbc {
                 0x6000..=0x7FFF => *bank_mode = (val & 0b1) > 0,                // 0より大きければ有効
                 _               => panic!("Not Define {:x}", addr),
             },
+            Self::Mbc3 {
+                ref mut sram_enable,
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ref mut rtc,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => *sram_enable = val & 0xF == 0xA,
+                0x2000..=0x3FFF => *rom_bank = if val & 0x7F == 0 { 1 } else { (val & 0x7F) as usize },
+                0x4000..=0x5FFF => *ram_bank = (val & 0xF) as usize,
+                0x6000..=0x7FFF => if let Some(rtc) = rtc { rtc.latch(val) },
+                _               => panic!("Not Define {:x}", addr),
+            },
+            Self::HuC1 {
+                ref mut ir_mode,
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => *ir_mode = val == 0x0E,                      // 0x0Eなら赤外線、それ以外はSRAM
+                0x2000..=0x3FFF => *rom_bank = (val & 0x3F) as usize,
+                0x4000..=0x5FFF => *ram_bank = (val & 0b11) as usize,
+                0x6000..=0x7FFF => {},
+                _               => panic!("Not Define {:x}", addr),
+            },
+            Self::HuC3 {
+                ref mut mode,
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => *mode = val & 0xF,
+                0x2000..=0x3FFF => *rom_bank = (val & 0x7F) as usize,
+                0x4000..=0x5FFF => *ram_bank = (val & 0b11) as usize,
+                0x6000..=0x7FFF => {},
+                _               => panic!("Not Define {:x}", addr),
+            },
+            Self::Mmm01 {
+                ref mut sram_enable,
+                ref mut locked,
+                ref mut rom_bank,
+                ref mut rom_mask,
+                ref mut ram_bank,
+                ref mut ram_mask,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => {
+                    *sram_enable = val & 0xF == 0xA;
+                    if !*locked {
+                        *ram_mask = ((val >> 4) & 0b11) as usize;
+                        *locked = val & 0x40 > 0;                           // bit6でマッピングを確定
+                    }
+                },
+                0x2000..=0x3FFF => {
+                    // ロック後はマスクされたビットと外側のバンクは変更できない
+                    let writable = if *locked { 0x1F & !(*rom_mask << 1) } else { 0x7F };
+                    *rom_bank = (*rom_bank & !writable) | (val as usize & writable);
+                },
+                0x4000..=0x5FFF => {
+                    let writable = if *locked { 0b11 & !*ram_mask } else { 0b1111 };
+                    *ram_bank = (*ram_bank & !writable) | (val as usize & writable);
+                    if !*locked {
+                        *rom_bank = (*rom_bank & 0x7F) | (((val >> 4) & 0b11) as usize) << 7;
+                    }
+                },
+                0x6000..=0x7FFF => if !*locked {
+                    *rom_mask = ((val >> 2) & 0b1111) as usize;
+                },
+                _               => panic!("Not Define {:x}", addr),
+            },
+            Self::Tama5 {
+                ref mut reg_select,
+                ref mut regs,
+                ..
+            } => match addr {
+                0x0000..=0x7FFF => {},
+                0xA000 => {
+                    regs[*reg_select] = val & 0xF;
+                    // レジスタ7への書き込みでコマンド実行
+                    if *reg_select == 0x7 {
+                        let ram_addr = (((regs[0x6] & 1) as usize) << 4) | regs[0x7] as usize;
+                        match regs[0x6] >> 1 {
+                            0x0 => sram[ram_addr] = (regs[0x5] << 4) | regs[0x4],   // SRAMへ書き込み
+                            0x1 => {                                                // SRAMから読み込み
+                                regs[0xC] = sram[ram_addr] & 0xF;
+                                regs[0xD] = sram[ram_addr] >> 4;
+                            },
+                            _   => {},                                              // RTCは未対応
+                        }
+                    }
+                },
+                0xA001 => *reg_select = (val & 0xF) as usize,
+                _      => {},
+            },
+            Self::Mbc6 {
+                ref mut sram_enable,
+                ref mut ram_banks,
+                ref mut rom_banks,
+                ref mut flash_select,
+                ref mut flash_enable,
+                ref mut flash_write_enable,
+                ref mut flash,
+                ram_size,
+                ..
+            } => match addr {
+                0x0000..=0x03FF => *sram_enable = val & 0xF == 0xA,
+                0x0400..=0x07FF => ram_banks[0] = (val & 0b111) as usize,
+                0x0800..=0x0BFF => ram_banks[1] = (val & 0b111) as usize,
+                0x0C00..=0x0FFF => *flash_enable = val & 1 > 0,
+                0x1000          => *flash_write_enable = val & 1 > 0,
+                0x1001..=0x1FFF => {},
+                0x2000..=0x27FF => rom_banks[0] = (val & 0x7F) as usize,
+                0x2800..=0x2FFF => flash_select[0] = val == 0x08,
+                0x3000..=0x37FF => rom_banks[1] = (val & 0x7F) as usize,
+                0x3800..=0x3FFF => flash_select[1] = val == 0x08,
+                0x4000..=0x7FFF => {
+                    // フラッシュへのコマンド
+                    let i = (addr as usize >> 13) & 1;
+                    if flash_select[i] && *flash_enable {
+                        let offset = (rom_banks[i] << 13) | (addr & 0x1FFF) as usize;
+                        flash.write(&mut sram[ram_size..], offset, val, *flash_write_enable);
+                    }
+                },
+                _               => panic!("Not Define {:x}", addr),
+            },
+            Self::Mbc7 {
+                ref mut sram_enable,
+                ref mut rom_bank,
+                ref mut accel,
+                ref mut latched,
+                ref mut eeprom,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => sram_enable[0] = val == 0x0A,
+                0x2000..=0x3FFF => *rom_bank = (val & 0x7F) as usize,
+                0x4000..=0x5FFF => sram_enable[1] = val == 0x40,
+                0x6000..=0x7FFF => {},
+                0xA000..=0xAFFF if sram_enable[0] && sram_enable[1] => match ((addr >> 4) & 0xF, val) {
+                    (0x0, 0x55) => *latched = (0x8000, 0x8000),                     // 消去
+                    (0x1, 0xAA) if *latched == (0x8000, 0x8000) => *latched = *accel,   // ラッチ
+                    (0x8, _)    => eeprom.write(sram, val),
+                    _           => {},
+                },
+                _               => {},
+            },
+            Self::PocketCamera {
+                ref mut sram_enable,
+                ref mut rom_bank,
+                ref mut ram_bank,
+                ..
+            } => match addr {
+                0x0000..=0x1FFF => *sram_enable = val & 0xF == 0xA,
+                0x2000..=0x3FFF => *rom_bank = (val & 0x3F) as usize,
+                0x4000..=0x5FFF => *ram_bank = (val & 0x1F) as usize,
+                0x6000..=0x7FFF => {},
+                _               => panic!("Not Define {:x}", addr),
+            },
+        }
+    }
+
+    // ROMの読み込み
+    pub fn read_rom(&self, rom: &[u8], sram: &[u8], addr: u16) -> u8 {
+        match *self {
+            Self::Mbc6 {
+                rom_banks,
+                flash_select,
+                flash_enable,
+                ref flash,
+                ram_size,
+                rom_len,
+                ..
+            } if addr >= 0x4000 => {
+                let i = (addr as usize >> 13) & 1;
+                let offset = (rom_banks[i] << 13) | (addr & 0x1FFF) as usize;
+                if flash_select[i] {
+                    if flash_enable { flash.read(&sram[ram_size..], offset) } else { 0xFF }
+                } else {
+                    rom[offset & (rom_len - 1)]
+                }
+            },
+            _ => rom[self.get_addr(addr) & (rom.len() - 1)],
+        }
+    }
+
+    // SRAM領域の読み込み
+    pub fn read_sram(&self, sram: &[u8], addr: u16) -> u8 {
+        match *self {
+            Self::RomOnly => self.sram_byte(sram, addr as usize),
+            Self::Mbc1 { sram_enable, .. } | Self::Mmm01 { sram_enable, .. } => if sram_enable {
+                self.sram_byte(sram, self.get_addr(addr))
+            } else {
+                0xFF
+            },
+            Self::Mbc3 { sram_enable, ram_bank, ref rtc, .. } => match ram_bank {
+                _ if !sram_enable => 0xFF,
+                0x08..=0x0C       => rtc.as_ref().map_or(0xFF, |rtc| rtc.read(ram_bank)),
+                _                 => self.sram_byte(sram, self.get_addr(addr)),
+            },
+            Self::HuC1 { ir_mode, ir_light, .. } => if ir_mode {
+                0xC0 | ir_light as u8                       // bit0が受光
+            } else {
+                self.sram_byte(sram, self.get_addr(addr))
+            },
+            Self::HuC3 { mode, ir_light, ref rtc, .. } => match mode {
+                0x0 | 0xA => self.sram_byte(sram, self.get_addr(addr)),
+                0xC       => rtc.read(),
+                0xD       => 0x01,                          // RTCは常に準備完了
+                0xE       => 0xC0 | ir_light as u8,
+                _         => 0xFF,
+            },
+            Self::Tama5 { reg_select, ref regs, .. } => match addr {
+                0xA000 => 0xF0 | regs[reg_select],
+                0xA001 => 0xF1,                             // 常に準備完了
+                _      => 0xFF,
+            },
+            Self::Mbc6 { sram_enable, ram_banks, ram_size, .. } => if sram_enable && ram_size > 0 {
+                let i = (addr as usize >> 12) & 1;
+                sram[((ram_banks[i] << 12) | (addr & 0xFFF) as usize) & (ram_size - 1)]
+            } else {
+                0xFF
+            },
+            Self::Mbc7 { sram_enable, latched, ref eeprom, .. } => {
+                if !(sram_enable[0] && sram_enable[1]) || addr >= 0xB000 {
+                    return 0xFF;
+                }
+                match (addr >> 4) & 0xF {
+                    0x2 => latched.0 as u8,
+                    0x3 => (latched.0 >> 8) as u8,
+                    0x4 => latched.1 as u8,
+                    0x5 => (latched.1 >> 8) as u8,
+                    0x6 => 0x00,
+                    0x8 => eeprom.read(),
+                    _   => 0xFF,
+                }
+            },
+            Self::PocketCamera { ram_bank, ref camera, .. } => if ram_bank & 0x10 > 0 {
+                camera.read(addr)
+            } else {
+                self.sram_byte(sram, self.get_addr(addr))
+            },
+        }
+    }
+
+    // SRAM領域の書き込み
+    pub fn write_sram(&mut self, sram: &mut [u8], addr: u16, val: u8) {
+        let sram_addr = self.get_addr(addr);
+        match *self {
+            Self::RomOnly => Self::set_sram_byte(sram, addr as usize, val),
+            Self::Mbc1 { sram_enable, .. } | Self::Mmm01 { sram_enable, .. } => if sram_enable {
+                Self::set_sram_byte(sram, sram_addr, val)
+            },
+            Self::Mbc3 { sram_enable, ram_bank, ref mut rtc, .. } => match ram_bank {
+                _ if !sram_enable => {},
+                0x08..=0x0C       => if let Some(rtc) = rtc { rtc.write(ram_bank, val) },
+                _                 => Self::set_sram_byte(sram, sram_addr, val),
+            },
+            Self::HuC1 { ir_mode, ref mut ir_led, .. } => if ir_mode {
+                *ir_led = val & 1 > 0;
+            } else {
+                Self::set_sram_byte(sram, sram_addr, val)
+            },
+            Self::HuC3 { mode, ref mut ir_led, ref mut rtc, .. } => match mode {
+                0xA => Self::set_sram_byte(sram, sram_addr, val),
+                0xB => rtc.write(val),
+                0xE => *ir_led = val & 1 > 0,
+                _   => {},
+            },
+            Self::Mbc6 { sram_enable, ram_banks, ram_size, .. } => if sram_enable && ram_size > 0 {
+                let i = (addr as usize >> 12) & 1;
+                sram[((ram_banks[i] << 12) | (addr & 0xFFF) as usize) & (ram_size - 1)] = val;
+            },
+            Self::PocketCamera { sram_enable, ram_bank, ref mut camera, .. } => if ram_bank & 0x10 > 0 {
+                camera.write(sram, addr, val);
+            } else if sram_enable {
+                Self::set_sram_byte(sram, sram_addr, val)
+            },
+            Self::Tama5 { .. } | Self::Mbc7 { .. } => self.write(sram, addr, val),
         }
     }
 
+    // SRAMが無い場合は0xFF
+    fn sram_byte(&self, sram: &[u8], addr: usize) -> u8 {
+        if sram.is_empty() { 0xFF } else { sram[addr & (sram.len() - 1)] }
+    }
+    fn set_sram_byte(sram: &mut [u8], addr: usize, val: u8) {
+        let len = sram.len();
+        if len > 0 { sram[addr & (len - 1)] = val; }
+    }
+
     // カードリッジ内のアドレス取得
     pub fn get_addr (&self, addr: u16) -> usize {
         match *self {
-            Mbc::NoMbc => addr as usize,
+            Mbc::RomOnly => addr as usize,
             Mbc::Mbc1 {
                 low_bank,
                 high_bank,
                 bank_mode,
                 rom_banks,
+                multicart,
+                sram_banked,
+                ..
+            } => {
+                // MBC1MはLOWバンクの bit4 が未配線で、HIGHバンクは bit18 から
+                let (low_bank, high_shift) = if multicart {
+                    (low_bank & 0b01111, 18)
+                } else {
+                    (low_bank, 19)
+                };
+                match addr {
+                    // 読み出しのみ
+                    0x0000..=0x3FFF => {
+                        if bank_mode {
+                            (high_bank << high_shift) | (addr & 0x3FFF) as usize
+                        } else {
+                            (addr & 0x3FFF) as usize
+                        }
+                    },
+                    // 読み出しのみ
+                    0x4000..=0x7FFF => {
+                        (high_bank << high_shift) | ((low_bank & (rom_banks -1)) << 14) | (addr & 0x3FFF) as usize
+                    },
+                    // 読み書き
+                    0xA000..=0xBFFF => {
+                        if bank_mode && sram_banked {
+                            (high_bank << 13) | (addr & 0x1FFF) as usize
+                        } else {
+                            (addr & 0x1FFF) as usize
+                        }
+                    },
+                    _ => panic!("Not Define {:x}", addr),
+                }
+            },
+            Mbc::Mbc3 { rom_bank, ram_bank, rom_banks, .. } => match addr {
+                0x0000..=0x3FFF => (addr & 0x3FFF) as usize,
+                0x4000..=0x7FFF => ((rom_bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize,
+                0xA000..=0xBFFF => ((ram_bank & 0b11) << 13) | (addr & 0x1FFF) as usize,
+                _ => panic!("Not Define {:x}", addr),
+            },
+            // HuC1・HuC3はバンク0の補正が無いこと以外はMBC1と同様
+            Mbc::HuC1 { rom_bank, ram_bank, rom_banks, .. }
+            | Mbc::HuC3 { rom_bank, ram_bank, rom_banks, .. } => match addr {
+                0x0000..=0x3FFF => (addr & 0x3FFF) as usize,
+                0x4000..=0x7FFF => ((rom_bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize,
+                0xA000..=0xBFFF => (ram_bank << 13) | (addr & 0x1FFF) as usize,
+                _ => panic!("Not Define {:x}", addr),
+            },
+            Mbc::Mmm01 {
+                locked,
+                rom_bank,
+                rom_mask,
+                ram_bank,
+                rom_banks,
                 ..
             } => match addr {
-                // 読み出しのみ
-                0x0000..=0x3FFF => {
-                    if bank_mode {
-                        (high_bank << 19) | (addr & 0x3FFF) as usize
-                    } else {
-                        (addr & 0x3FFF) as usize
-                    }
+                // ロック前は末尾32KBのメニューが見える
+                0x0000..=0x3FFF => if locked {
+                    let outer = rom_bank & !(0x1F & !(rom_mask << 1));
+                    ((outer & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize
+                } else {
+                    ((rom_banks - 2) << 14) | (addr & 0x3FFF) as usize
                 },
-                // 読み出しのみ
-                0x4000..=0x7FFF => {
-                    (high_bank << 19) | ((low_bank & (rom_banks -1)) << 14) | (addr & 0x3FFF) as usize
+                0x4000..=0x7FFF => if locked {
+                    let bank = if rom_bank & 0x1F == 0 { rom_bank | 1 } else { rom_bank };
+                    ((bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize
+                } else {
+                    ((rom_banks - 1) << 14) | (addr & 0x3FFF) as usize
                 },
-                // 読み書き
-                0xA000..=0xBFFF => {
-                    if bank_mode {
-                        (high_bank << 13) | (addr & 0x1FFF) as usize
-                    } else {
-                        (addr & 0x1FFF) as usize
-                    }
+                0xA000..=0xBFFF => (ram_bank << 13) | (addr & 0x1FFF) as usize,
+                _ => panic!("Not Define {:x}", addr),
+            },
+            Mbc::Tama5 { ref regs, rom_banks, .. } => match addr {
+                0x0000..=0x3FFF => (addr & 0x3FFF) as usize,
+                0x4000..=0x7FFF => {
+                    let bank = (((regs[0x1] & 1) << 4) | regs[0x0]) as usize;
+                    ((bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize
                 },
+                _ => addr as usize & 0x1F,
+            },
+            Mbc::Mbc6 { .. } => (addr & 0x3FFF) as usize,
+            Mbc::PocketCamera { rom_bank, ram_bank, rom_banks, .. } => match addr {
+                0x0000..=0x3FFF => (addr & 0x3FFF) as usize,
+                0x4000..=0x7FFF => ((rom_bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize,
+                0xA000..=0xBFFF => ((ram_bank & 0xF) << 13) | (addr & 0x1FFF) as usize,
                 _ => panic!("Not Define {:x}", addr),
-            }
+            },
+            Mbc::Mbc7 { rom_bank, rom_banks, .. } => match addr {
+                0x0000..=0x3FFF => (addr & 0x3FFF) as usize,
+                0x4000..=0x7FFF => ((rom_bank & (rom_banks - 1)) << 14) | (addr & 0x3FFF) as usize,
+                _ => addr as usize,
+            },
+        }
+    }
+}
+
+// ROMから決まる値（バンク数など）は保存しない
+impl Snapshot for Mbc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(self.name().as_bytes());
+        match *self {
+            Self::RomOnly => (),
+            Self::Mbc1 { sram_enable, low_bank, high_bank, bank_mode, .. } => {
+                w.bool(sram_enable);
+                w.usize(low_bank);
+                w.usize(high_bank);
+                w.bool(bank_mode);
+            },
+            Self::Mbc3 { sram_enable, rom_bank, ram_bank, ref rtc, .. } => {
+                w.bool(sram_enable);
+                w.usize(rom_bank);
+                w.usize(ram_bank);
+                if let Some(ref rtc) = *rtc {
+                    rtc.save_state(w);
+                }
+            },
+            Self::HuC1 { ir_mode, ir_led, ir_light, rom_bank, ram_bank, .. } => {
+                w.bool(ir_mode);
+                w.bool(ir_led);
+                w.bool(ir_light);
+                w.usize(rom_bank);
+                w.usize(ram_bank);
+            },
+            Self::HuC3 { mode, ir_led, ir_light, rom_bank, ram_bank, ref rtc, .. } => {
+                w.u8(mode);
+                w.bool(ir_led);
+                w.bool(ir_light);
+                w.usize(rom_bank);
+                w.usize(ram_bank);
+                rtc.save_state(w);
+            },
+            Self::Mmm01 { sram_enable, locked, rom_bank, rom_mask, ram_bank, ram_mask, .. } => {
+                w.bool(sram_enable);
+                w.bool(locked);
+                w.usize(rom_bank);
+                w.usize(rom_mask);
+                w.usize(ram_bank);
+                w.usize(ram_mask);
+            },
+            Self::Tama5 { reg_select, ref regs, .. } => {
+                w.usize(reg_select);
+                w.bytes(regs);
+            },
+            Self::Mbc6 { sram_enable, ram_banks, rom_banks, flash_select, flash_enable, flash_write_enable, ref flash, .. } => {
+                w.bool(sram_enable);
+                for bank in ram_banks.into_iter().chain(rom_banks) {
+                    w.usize(bank);
+                }
+                for select in flash_select {
+                    w.bool(select);
+                }
+                w.bool(flash_enable);
+                w.bool(flash_write_enable);
+                flash.save_state(w);
+            },
+            Self::Mbc7 { sram_enable, rom_bank, accel, latched, ref eeprom, .. } => {
+                w.bool(sram_enable[0]);
+                w.bool(sram_enable[1]);
+                w.usize(rom_bank);
+                w.u16(accel.0);
+                w.u16(accel.1);
+                w.u16(latched.0);
+                w.u16(latched.1);
+                eeprom.save_state(w);
+            },
+            Self::PocketCamera { sram_enable, rom_bank, ram_bank, ref camera, .. } => {
+                w.bool(sram_enable);
+                w.usize(rom_bank);
+                w.usize(ram_bank);
+                camera.save_state(w);
+            },
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        if r.bytes()? != self.name().as_bytes() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "mbc type mismatch"));
+        }
+        match *self {
+            Self::RomOnly => (),
+            Self::Mbc1 { ref mut sram_enable, ref mut low_bank, ref mut high_bank, ref mut bank_mode, .. } => {
+                *sram_enable = r.bool()?;
+                *low_bank = r.usize()?;
+                *high_bank = r.usize()?;
+                *bank_mode = r.bool()?;
+            },
+            Self::Mbc3 { ref mut sram_enable, ref mut rom_bank, ref mut ram_bank, ref mut rtc, .. } => {
+                *sram_enable = r.bool()?;
+                *rom_bank = r.usize()?;
+                *ram_bank = r.usize()?;
+                if let Some(ref mut rtc) = *rtc {
+                    rtc.load_state(r)?;
+                }
+            },
+            Self::HuC1 { ref mut ir_mode, ref mut ir_led, ref mut ir_light, ref mut rom_bank, ref mut ram_bank, .. } => {
+                *ir_mode = r.bool()?;
+                *ir_led = r.bool()?;
+                *ir_light = r.bool()?;
+                *rom_bank = r.usize()?;
+                *ram_bank = r.usize()?;
+            },
+            Self::HuC3 { ref mut mode, ref mut ir_led, ref mut ir_light, ref mut rom_bank, ref mut ram_bank, ref mut rtc, .. } => {
+                *mode = r.u8()?;
+                *ir_led = r.bool()?;
+                *ir_light = r.bool()?;
+                *rom_bank = r.usize()?;
+                *ram_bank = r.usize()?;
+                rtc.load_state(r)?;
+            },
+            Self::Mmm01 { ref mut sram_enable, ref mut locked, ref mut rom_bank, ref mut rom_mask, ref mut ram_bank, ref mut ram_mask, .. } => {
+                *sram_enable = r.bool()?;
+                *locked = r.bool()?;
+                *rom_bank = r.usize()?;
+                *rom_mask = r.usize()?;
+                *ram_bank = r.usize()?;
+                *ram_mask = r.usize()?;
+            },
+            Self::Tama5 { ref mut reg_select, ref mut regs, .. } => {
+                *reg_select = r.usize()? & 0x0F;
+                r.bytes_into(regs)?;
+            },
+            Self::Mbc6 { ref mut sram_enable, ref mut ram_banks, ref mut rom_banks, ref mut flash_select, ref mut flash_enable, ref mut flash_write_enable, ref mut flash, .. } => {
+                *sram_enable = r.bool()?;
+                for bank in ram_banks.iter_mut().chain(rom_banks.iter_mut()) {
+                    *bank = r.usize()?;
+                }
+                for select in flash_select.iter_mut() {
+                    *select = r.bool()?;
+                }
+                *flash_enable = r.bool()?;
+                *flash_write_enable = r.bool()?;
+                flash.load_state(r)?;
+            },
+            Self::Mbc7 { ref mut sram_enable, ref mut rom_bank, ref mut accel, ref mut latched, ref mut eeprom, .. } => {
+                sram_enable[0] = r.bool()?;
+                sram_enable[1] = r.bool()?;
+                *rom_bank = r.usize()?;
+                *accel = (r.u16()?, r.u16()?);
+                *latched = (r.u16()?, r.u16()?);
+                eeprom.load_state(r)?;
+            },
+            Self::PocketCamera { ref mut sram_enable, ref mut rom_bank, ref mut ram_bank, ref mut camera, .. } => {
+                *sram_enable = r.bool()?;
+                *rom_bank = r.usize()?;
+                *ram_bank = r.usize()?;
+                camera.load_state(r)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各バンクの全byteがバンク番号のROM
+    fn rom(len: usize, bank_size: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / bank_size) as u8).collect()
+    }
+
+    fn mbc(cartridge_type: u8, rom: &[u8], header_sram_size: usize) -> (Mbc, Vec<u8>) {
+        let mbc = Mbc::new(cartridge_type, rom.len() >> 14, header_sram_size, false).unwrap();
+        let sram = vec![0; mbc.sram_size(header_sram_size)];
+        (mbc, sram)
+    }
+
+    // MBC1M：0x40000 毎のロゴで判定し、HIGHバンクは bit18 から、LOWバンクの bit4 は未配線
+    #[test]
+    fn mbc1_multicart() {
+        let mut rom = rom(0x100000, 0x4000);
+        assert!(!is_mbc1_multicart(&rom));
+        for game in 0..4 {
+            let addr = game * 0x40000 + 0x104;
+            rom[addr..addr + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        assert!(is_mbc1_multicart(&rom));
+        assert!(!is_mbc1_multicart(&rom[..0x80000]));
+
+        let mut sram = Vec::new();
+        let mut mbc = Mbc::new(0x01, rom.len() >> 14, 0, true).unwrap();
+        assert_eq!(mbc.name(), "MBC1M");
+        mbc.write(&mut sram, 0x2000, 0x12);
+        mbc.write(&mut sram, 0x4000, 0x01);
+        assert_eq!(mbc.get_addr(0x4000), 0x48000);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x12);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x00);
+        // バンクモード1では 0x0000～0x3FFF もHIGHバンクで切り替わる（2つ目のゲームの先頭）
+        mbc.write(&mut sram, 0x6000, 0x01);
+        assert_eq!(mbc.get_addr(0x0000), 0x40000);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x10);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0104), NINTENDO_LOGO[0]);
+
+        // 通常のMBC1はHIGHバンクが bit19 から
+        let mut mbc = Mbc::new(0x01, rom.len() >> 14, 0, false).unwrap();
+        mbc.write(&mut sram, 0x2000, 0x12);
+        mbc.write(&mut sram, 0x4000, 0x01);
+        assert_eq!(mbc.get_addr(0x4000), 0xC8000);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x32);
+    }
+
+    // HIGHバンクでSRAMを切り替えるのはSRAMが8KBより大きい場合のみ
+    #[test]
+    fn mbc1_sram_banking() {
+        let rom = rom(0x100000, 0x4000);
+        for (size, offset) in [(0x2000, 0x0123), (0x8000, 0x6123)] {
+            let mut mbc = Mbc::new(0x03, rom.len() >> 14, size, true).unwrap();
+            let mut sram = vec![0; size];
+            mbc.write(&mut sram, 0x0000, 0x0A);
+            mbc.write(&mut sram, 0x6000, 0x01);
+            mbc.write(&mut sram, 0x4000, 0x03);
+            mbc.write_sram(&mut sram, 0xA123, 0x5A);
+            assert_eq!(sram[offset], 0x5A, "sram {:x}", size);
+            assert_eq!(mbc.read_sram(&sram, 0xA123), 0x5A);
+            // HIGHバンクはROMの切り替えにも使われる
+            assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x30);
+        }
+    }
+
+    #[test]
+    fn huc1_banks_and_ir() {
+        let rom = rom(0x20000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0xFF, &rom, 0x8000);
+        mbc.write(&mut sram, 0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x05);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x00);
+
+        mbc.write(&mut sram, 0x4000, 0x02);
+        mbc.write_sram(&mut sram, 0xA123, 0x77);
+        assert_eq!(sram[0x4123], 0x77);
+        assert_eq!(mbc.read_sram(&sram, 0xA123), 0x77);
+
+        // 0x0Eで赤外線ポートに切り替わる
+        mbc.write(&mut sram, 0x0000, 0x0E);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0xC0);
+        mbc.set_ir_light(true);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0xC1);
+        mbc.write_sram(&mut sram, 0xA000, 0x01);
+        assert_eq!(mbc.ir_led(), Some(true));
+        assert_eq!(sram[0x4000], 0x00);
+    }
+
+    #[test]
+    fn huc3_banks_and_modes() {
+        let rom = rom(0x40000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0xFE, &rom, 0x8000);
+        mbc.write(&mut sram, 0x2000, 0x0C);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x7FFF), 0x0C);
+
+        // 0x0Aで読み書き、0x00は読み込みのみ
+        mbc.write(&mut sram, 0x4000, 0x03);
+        mbc.write(&mut sram, 0x0000, 0x0A);
+        mbc.write_sram(&mut sram, 0xA010, 0x5A);
+        assert_eq!(sram[0x6010], 0x5A);
+        mbc.write(&mut sram, 0x0000, 0x00);
+        mbc.write_sram(&mut sram, 0xA010, 0x00);
+        assert_eq!(mbc.read_sram(&sram, 0xA010), 0x5A);
+
+        mbc.write(&mut sram, 0x0000, 0x0D);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0x01);
+    }
+
+    #[test]
+    fn mmm01_menu_and_lock() {
+        let rom = rom(0x80000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0x0B, &rom, 0x8000);
+        // ロック前は末尾32KBのメニュー
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x1E);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x1F);
+
+        // 外側のバンク 0x10 のゲームを選んでロックする
+        mbc.write(&mut sram, 0x2000, 0x10);
+        mbc.write(&mut sram, 0x6000, 0x3C);     // 内側のバンクは下位1bitのみ
+        mbc.write(&mut sram, 0x0000, 0x4A);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x10);
+        mbc.write(&mut sram, 0x2000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x11);
+        mbc.write(&mut sram, 0x2000, 0x1E);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x10);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x0000), 0x10);
+
+        mbc.write(&mut sram, 0x4000, 0x01);
+        mbc.write_sram(&mut sram, 0xA000, 0x66);
+        assert_eq!(sram[0x2000], 0x66);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0x66);
+    }
+
+    // TAMA5のレジスタへの書き込み
+    fn tama5_write(mbc: &mut Mbc, sram: &mut [u8], reg: u8, val: u8) {
+        mbc.write_sram(sram, 0xA001, reg);
+        mbc.write_sram(sram, 0xA000, val);
+    }
+
+    #[test]
+    fn tama5_banks_and_ram() {
+        let rom = rom(0x80000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0xFD, &rom, 0);
+        tama5_write(&mut mbc, &mut sram, 0x0, 0x5);
+        tama5_write(&mut mbc, &mut sram, 0x1, 0x1);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x15);
+
+        // アドレス 0x12 へ 0x3C を書き込み、読み出す
+        tama5_write(&mut mbc, &mut sram, 0x4, 0xC);
+        tama5_write(&mut mbc, &mut sram, 0x5, 0x3);
+        tama5_write(&mut mbc, &mut sram, 0x6, 0x1);
+        tama5_write(&mut mbc, &mut sram, 0x7, 0x2);
+        assert_eq!(sram[0x12], 0x3C);
+        tama5_write(&mut mbc, &mut sram, 0x6, 0x3);
+        tama5_write(&mut mbc, &mut sram, 0x7, 0x2);
+        mbc.write_sram(&mut sram, 0xA001, 0xC);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0xFC);
+        mbc.write_sram(&mut sram, 0xA001, 0xD);
+        assert_eq!(mbc.read_sram(&sram, 0xA000), 0xF3);
+    }
+
+    #[test]
+    fn mbc6_banks() {
+        let rom = rom(0x40000, 0x2000);
+        let (mut mbc, mut sram) = mbc(0x20, &rom, 0x8000);
+        mbc.write(&mut sram, 0x2000, 0x05);
+        mbc.write(&mut sram, 0x3000, 0x1A);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x05);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x6000), 0x1A);
+
+        mbc.write(&mut sram, 0x0000, 0x0A);
+        mbc.write(&mut sram, 0x0400, 0x03);
+        mbc.write(&mut sram, 0x0800, 0x05);
+        mbc.write_sram(&mut sram, 0xA010, 0x44);
+        mbc.write_sram(&mut sram, 0xB020, 0x55);
+        assert_eq!(sram[0x3010], 0x44);
+        assert_eq!(sram[0x5020], 0x55);
+        assert_eq!(mbc.read_sram(&sram, 0xB020), 0x55);
+    }
+
+    // MBC6のフラッシュへのコマンド列（0x4000側はバンク2、0x6000側はバンク1）
+    fn flash_unlock(mbc: &mut Mbc, sram: &mut [u8], cmd: u8) {
+        mbc.write(sram, 0x5555, 0xAA);
+        mbc.write(sram, 0x6AAA, 0x55);
+        mbc.write(sram, 0x5555, cmd);
+    }
+
+    #[test]
+    fn mbc6_flash_commands() {
+        let rom = rom(0x40000, 0x2000);
+        let (mut mbc, mut sram) = mbc(0x20, &rom, 0x8000);
+        mbc.write(&mut sram, 0x0C00, 0x01);
+        mbc.write(&mut sram, 0x1000, 0x01);
+        mbc.write(&mut sram, 0x2000, 0x02);
+        mbc.write(&mut sram, 0x2800, 0x08);
+        mbc.write(&mut sram, 0x3000, 0x01);
+        mbc.write(&mut sram, 0x3800, 0x08);
+
+        // セクタ消去
+        flash_unlock(&mut mbc, &mut sram, 0x80);
+        mbc.write(&mut sram, 0x5555, 0xAA);
+        mbc.write(&mut sram, 0x6AAA, 0x55);
+        mbc.write(&mut sram, 0x4100, 0x30);
+        assert!(sram[0x8000..0x8000 + 0x20000].iter().all(|&b| b == 0xFF));
+        assert_eq!(sram[0x8000 + 0x20000], 0x00);
+
+        // 書き込み
+        flash_unlock(&mut mbc, &mut sram, 0xA0);
+        mbc.write(&mut sram, 0x4100, 0x5A);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4100), 0x5A);
+        assert_eq!(sram[0x8000 + 0x4100], 0x5A);
+
+        // 書き込み禁止の間は変わらない
+        mbc.write(&mut sram, 0x1000, 0x00);
+        flash_unlock(&mut mbc, &mut sram, 0xA0);
+        mbc.write(&mut sram, 0x4101, 0x00);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4101), 0xFF);
+
+        // フラッシュが無効ならROMの代わりに0xFF
+        mbc.write(&mut sram, 0x0C00, 0x00);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4100), 0xFF);
+    }
+
+    #[test]
+    fn mbc7_banks_and_accelerometer() {
+        let rom = rom(0x40000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0x22, &rom, 0);
+        mbc.write(&mut sram, 0x2000, 0x09);
+        assert_eq!(mbc.read_rom(&rom, &sram, 0x4000), 0x09);
+
+        // 両方で有効化するまで読めない
+        mbc.write(&mut sram, 0x0000, 0x0A);
+        assert_eq!(mbc.read_sram(&sram, 0xA020), 0xFF);
+        mbc.write(&mut sram, 0x4000, 0x40);
+        mbc.set_accelerometer(1.0, 0.0);
+        mbc.write_sram(&mut sram, 0xA000, 0x55);
+        mbc.write_sram(&mut sram, 0xA010, 0xAA);
+        assert_eq!(mbc.read_sram(&sram, 0xA020), 0x40);
+        assert_eq!(mbc.read_sram(&sram, 0xA030), 0x82);
+        assert_eq!(mbc.read_sram(&sram, 0xA040), 0xD0);
+        assert_eq!(mbc.read_sram(&sram, 0xA050), 0x81);
+    }
+
+    // EEPROMへビット列を送る（クロックの立ち上がりで取り込まれる）
+    fn eeprom_send(mbc: &mut Mbc, sram: &mut [u8], bits: u32, len: u32) {
+        for i in (0..len).rev() {
+            let di = if (bits >> i) & 1 > 0 { 0x02 } else { 0x00 };
+            mbc.write_sram(sram, 0xA080, 0x80 | di);
+            mbc.write_sram(sram, 0xA080, 0xC0 | di);
         }
     }
-}
\ No newline at end of file
+
+    // スタートビット、オペコード2bit、アドレス8bit
+    fn eeprom_command(mbc: &mut Mbc, sram: &mut [u8], opcode: u32, addr: u32) {
+        eeprom_send(mbc, sram, (1 << 10) | (opcode << 8) | addr, 11);
+    }
+
+    fn eeprom_end(mbc: &mut Mbc, sram: &mut [u8]) {
+        mbc.write_sram(sram, 0xA080, 0x00);
+    }
+
+    #[test]
+    fn mbc7_eeprom_commands() {
+        let rom = rom(0x40000, 0x4000);
+        let (mut mbc, mut sram) = mbc(0x22, &rom, 0);
+        mbc.write(&mut sram, 0x0000, 0x0A);
+        mbc.write(&mut sram, 0x4000, 0x40);
+
+        // 書き込み禁止の間は書けない
+        eeprom_command(&mut mbc, &mut sram, 0b01, 0x03);
+        eeprom_send(&mut mbc, &mut sram, 0xBEEF, 16);
+        eeprom_end(&mut mbc, &mut sram);
+        assert_eq!(&sram[6..8], &[0x00, 0x00]);
+
+        // EWEN、WRITE（アドレス3に0xBEEF）
+        eeprom_command(&mut mbc, &mut sram, 0b00, 0xC0);
+        eeprom_end(&mut mbc, &mut sram);
+        eeprom_command(&mut mbc, &mut sram, 0b01, 0x03);
+        eeprom_send(&mut mbc, &mut sram, 0xBEEF, 16);
+        eeprom_end(&mut mbc, &mut sram);
+        assert_eq!(&sram[6..8], &[0xEF, 0xBE]);
+
+        // READ、ダミーの0に続いて上位bitから出力される
+        eeprom_command(&mut mbc, &mut sram, 0b10, 0x03);
+        assert_eq!(mbc.read_sram(&sram, 0xA080) & 1, 0);
+        let mut word = 0u16;
+        for _ in 0..16 {
+            eeprom_send(&mut mbc, &mut sram, 0, 1);
+            word = (word << 1) | (mbc.read_sram(&sram, 0xA080) & 1) as u16;
+        }
+        eeprom_end(&mut mbc, &mut sram);
+        assert_eq!(word, 0xBEEF);
+
+        // ERAL
+        eeprom_command(&mut mbc, &mut sram, 0b00, 0x80);
+        eeprom_end(&mut mbc, &mut sram);
+        assert!(sram.iter().all(|&b| b == 0xFF));
+    }
+}
@@ -0,0 +1,118 @@
+// ポケットカメラのイメージセンサー（M64282FP）
+// レジスタは 0xA000-0xA035、撮影結果はSRAMの 0x0100 からタイル形式で書き込まれる
+
+use std::io;
+
+use crate::{
+    camera::{ImageSource, TestPattern, Pattern, CAMERA_WIDTH, CAMERA_HEIGHT},
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const IMAGE_START: usize = 0x0100;
+const EXPOSURE_UNIT: u32 = 0x0800;          // この露光時間で入力画像そのままの明るさ
+
+// 輪郭強調の強さ（A004 bit4-6）、1/4単位
+const EDGE_RATIO: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+pub struct Camera {
+    regs: [u8; 0x36],
+    source: Box<dyn ImageSource>,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 0x36],
+            source: Box::new(TestPattern::new(Pattern::Gradient)),
+        }
+    }
+
+    pub fn set_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+
+    // レジスタの読み込み、A000以外は読めない
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr & 0x7F {
+            0x00 => self.regs[0],
+            _    => 0x00,
+        }
+    }
+
+    // レジスタの書き込み、A000のbit0で撮影開始
+    // 撮影は書き込み時点で完了させる
+    pub fn write(&mut self, sram: &mut [u8], addr: u16, val: u8) {
+        let reg = (addr & 0x7F) as usize;
+        if reg < self.regs.len() {
+            self.regs[reg] = val;
+        }
+        if reg == 0 && val & 1 > 0 {
+            self.capture(sram);
+            self.regs[0] &= !1;
+        }
+    }
+
+    fn capture(&mut self, sram: &mut [u8]) {
+        let frame = self.source.frame();
+        let exposure = ((self.regs[2] as u32) << 8) | self.regs[3] as u32;
+        let edge = self.regs[1] & 0x60 == 0x60;     // VH=3 で縦横の輪郭強調
+        let ratio = EDGE_RATIO[((self.regs[4] >> 4) & 0b111) as usize];
+
+        // 露光
+        let exposed: Vec<i32> = frame.iter()
+            .map(|&p| (p as u32 * exposure / EXPOSURE_UNIT).min(255) as i32)
+            .collect();
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+            exposed[y * CAMERA_WIDTH + x]
+        };
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (xi, yi) = (x as isize, y as isize);
+                let mut v = at(xi, yi);
+                // 輪郭強調
+                if edge {
+                    let around = at(xi - 1, yi) + at(xi + 1, yi) + at(xi, yi - 1) + at(xi, yi + 1);
+                    v += (4 * v - around) * ratio / 4;
+                }
+                self.put_pixel(sram, x, y, self.dither(x, y, v.clamp(0, 255) as u8));
+            }
+        }
+    }
+
+    // ディザマトリクス（4x4、各3段階の閾値）で2bitの色にする、0が白
+    fn dither(&self, x: usize, y: usize, val: u8) -> u8 {
+        let i = 6 + ((y & 3) * 4 + (x & 3)) * 3;
+        match val {
+            v if v < self.regs[i]     => 3,
+            v if v < self.regs[i + 1] => 2,
+            v if v < self.regs[i + 2] => 1,
+            _                         => 0,
+        }
+    }
+
+    fn put_pixel(&self, sram: &mut [u8], x: usize, y: usize, color: u8) {
+        let offset = IMAGE_START + ((y >> 3) * (CAMERA_WIDTH >> 3) + (x >> 3)) * 16 + (y & 7) * 2;
+        let bit = 1 << (7 - (x & 7));
+        for (plane, mask) in [(0, 0b01), (1, 0b10)] {
+            if color & mask > 0 {
+                sram[offset + plane] |= bit;
+            } else {
+                sram[offset + plane] &= !bit;
+            }
+        }
+    }
+}
+
+// 入力画像の取得元は保存しない
+impl Snapshot for Camera {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.regs)
+    }
+}
@@ -0,0 +1,183 @@
+// MBC7のEEPROM（93LC56、16bit x 128ワード）
+// 0xAx8x の各ビットがEEPROMの端子に接続されている
+
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+const CS: u8 = 1 << 7;      // チップセレクト
+const CLK: u8 = 1 << 6;     // クロック
+const DI: u8 = 1 << 1;      // データ入力
+const DO: u8 = 1 << 0;      // データ出力
+
+enum State {
+    Idle,                               // スタートビット待ち
+    Command,                            // コマンド受信中
+    Write { addr: Option<usize> },      // 書き込みデータ受信中、Noneなら全ワード
+    Read { data: u16, remaining: u8 },  // 読み出し中
+}
+
+pub struct Eeprom {
+    pins: u8,
+    data_out: bool,
+    write_enable: bool,
+    shift: u16,
+    bits: u8,
+    state: State,
+}
+
+impl Eeprom {
+    pub fn new() -> Self {
+        Self {
+            pins: 0,
+            data_out: true,
+            write_enable: false,
+            shift: 0,
+            bits: 0,
+            state: State::Idle,
+        }
+    }
+
+    // 端子の状態を読み出す
+    pub fn read(&self) -> u8 {
+        (self.pins & (CS | CLK | DI)) | self.data_out as u8
+    }
+
+    // 端子の状態を書き込む、クロックの立ち上がりで1bit処理する
+    pub fn write(&mut self, sram: &mut [u8], val: u8) {
+        let rising = self.pins & CLK == 0 && val & CLK > 0;
+        self.pins = val;
+        if val & CS == 0 {
+            self.state = State::Idle;
+            return;
+        }
+        if rising {
+            self.clock(sram, val & DI > 0);
+        }
+    }
+
+    fn clock(&mut self, sram: &mut [u8], di: bool) {
+        match self.state {
+            State::Idle => if di {
+                self.shift = 0;
+                self.bits = 0;
+                self.state = State::Command;
+            },
+            State::Command => {
+                self.shift = (self.shift << 1) | di as u16;
+                self.bits += 1;
+                // オペコード2bit + アドレス8bit
+                if self.bits == 10 {
+                    self.command(sram);
+                }
+            },
+            State::Write { addr } => {
+                self.shift = (self.shift << 1) | di as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.write_enable {
+                        match addr {
+                            Some(addr) => Self::set_word(sram, addr, self.shift),
+                            None       => (0..0x80).for_each(|i| Self::set_word(sram, i, self.shift)),
+                        }
+                    }
+                    self.data_out = true;
+                    self.state = State::Idle;
+                }
+            },
+            State::Read { data, remaining } => {
+                self.data_out = data & 0x8000 > 0;
+                self.state = if remaining > 1 {
+                    State::Read { data: data << 1, remaining: remaining - 1 }
+                } else {
+                    State::Idle
+                };
+            },
+        }
+    }
+
+    fn command(&mut self, sram: &mut [u8]) {
+        let opcode = (self.shift >> 8) & 0b11;
+        let sub = (self.shift >> 6) & 0b11;     // オペコードが00の場合はアドレス上位2bitで区別
+        let addr = (self.shift & 0x7F) as usize;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = State::Idle;
+        match (opcode, sub) {
+            // READ、最初にダミーの0が出力される
+            (0b10, _) => {
+                self.data_out = false;
+                self.state = State::Read { data: Self::word(sram, addr), remaining: 16 };
+            },
+            // WRITE
+            (0b01, _) => self.state = State::Write { addr: Some(addr) },
+            // ERASE
+            (0b11, _) => {
+                if self.write_enable { Self::set_word(sram, addr, 0xFFFF); }
+                self.data_out = true;
+            },
+            // EWDS
+            (0b00, 0b00) => self.write_enable = false,
+            // WRAL
+            (0b00, 0b01) => self.state = State::Write { addr: None },
+            // ERAL
+            (0b00, 0b10) => {
+                if self.write_enable { (0..0x80).for_each(|i| Self::set_word(sram, i, 0xFFFF)); }
+                self.data_out = true;
+            },
+            // EWEN
+            _ => self.write_enable = true,
+        }
+    }
+
+    fn word(sram: &[u8], addr: usize) -> u16 {
+        u16::from_le_bytes([sram[addr << 1], sram[(addr << 1) | 1]])
+    }
+    fn set_word(sram: &mut [u8], addr: usize, val: u16) {
+        sram[addr << 1..(addr << 1) + 2].copy_from_slice(&val.to_le_bytes());
+    }
+}
+
+impl Snapshot for Eeprom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.pins);
+        w.bool(self.data_out);
+        w.bool(self.write_enable);
+        w.u16(self.shift);
+        w.u8(self.bits);
+        match self.state {
+            State::Idle => w.u8(0),
+            State::Command => w.u8(1),
+            State::Write { addr } => {
+                w.u8(2);
+                w.bool(addr.is_some());
+                w.usize(addr.unwrap_or(0));
+            },
+            State::Read { data, remaining } => {
+                w.u8(3);
+                w.u16(data);
+                w.u8(remaining);
+            },
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.pins = r.u8()?;
+        self.data_out = r.bool()?;
+        self.write_enable = r.bool()?;
+        self.shift = r.u16()?;
+        self.bits = r.u8()?;
+        self.state = match r.u8()? {
+            0 => State::Idle,
+            1 => State::Command,
+            2 => {
+                let some = r.bool()?;
+                let addr = r.usize()? & 0x7F;
+                State::Write { addr: some.then_some(addr) }
+            },
+            3 => State::Read { data: r.u16()?, remaining: r.u8()? },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid eeprom state")),
+        };
+        Ok(())
+    }
+}
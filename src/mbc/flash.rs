@@ -0,0 +1,90 @@
+// MBC6のフラッシュメモリ（MX29F008、1MB）
+// 0x5555 と 0x2AAA へのコマンド列で書き込み・消去を行う
+
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+pub const FLASH_SIZE: usize = 0x100000;
+const SECTOR_SIZE: usize = 0x20000;         // 128KB
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    Unlock1,            // 0xAA を受信
+    Unlock2,            // 0x55 を受信
+    Program,            // 次の書き込みでデータを書く
+    Erase,              // 0x80 を受信
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+pub struct Flash {
+    state: State,
+}
+
+impl Flash {
+    pub fn new() -> Self {
+        Self { state: State::Ready }
+    }
+
+    // 読み込み
+    pub fn read(&self, flash: &[u8], offset: usize) -> u8 {
+        flash[offset & (FLASH_SIZE - 1)]
+    }
+
+    // コマンドの書き込み
+    pub fn write(&mut self, flash: &mut [u8], offset: usize, val: u8, write_enable: bool) {
+        let offset = offset & (FLASH_SIZE - 1);
+        let cmd_addr = offset & 0x7FFF;
+        self.state = match (self.state, cmd_addr, val) {
+            (_, _, 0xF0) => State::Ready,                                   // リセット
+            (State::Ready, 0x5555, 0xAA) => State::Unlock1,
+            (State::Unlock1, 0x2AAA, 0x55) => State::Unlock2,
+            (State::Unlock2, 0x5555, 0xA0) => State::Program,
+            (State::Unlock2, 0x5555, 0x80) => State::Erase,
+            (State::Erase, 0x5555, 0xAA) => State::EraseUnlock1,
+            (State::EraseUnlock1, 0x2AAA, 0x55) => State::EraseUnlock2,
+            (State::EraseUnlock2, 0x5555, 0x10) => {                        // チップ消去
+                if write_enable { flash.fill(0xFF); }
+                State::Ready
+            },
+            (State::EraseUnlock2, _, 0x30) => {                             // セクタ消去
+                if write_enable {
+                    let start = offset & !(SECTOR_SIZE - 1);
+                    flash[start..start + SECTOR_SIZE].fill(0xFF);
+                }
+                State::Ready
+            },
+            (State::Program, _, _) => {
+                // フラッシュは1を0にすることしかできない
+                if write_enable { flash[offset] &= val; }
+                State::Ready
+            },
+            _ => State::Ready,
+        };
+    }
+}
+
+// フラッシュの内容はSRAMと一緒に保存される
+impl Snapshot for Flash {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.state as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        const STATES: [State; 7] = [
+            State::Ready,
+            State::Unlock1,
+            State::Unlock2,
+            State::Program,
+            State::Erase,
+            State::EraseUnlock1,
+            State::EraseUnlock2,
+        ];
+        self.state = *STATES
+            .get(r.u8()? as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid flash state"))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+// HuC3のRTC
+// 4bit単位のメモリを持ち、0x00-0x02 に分、0x03-0x05 に日が格納される
+
+use std::io;
+
+use crate::{
+    mbc::rtc::system_secs,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const SECS_PER_MINUTE: u64 = 60;
+const MINUTES_PER_DAY: u64 = 1440;
+
+pub struct HuC3Rtc {
+    memory: Vec<u8>,        // 4bit x 256
+    addr: usize,
+    command: u8,
+    response: u8,
+    base: u64,              // 時刻0に対応するUNIX時間[s]
+    clock: Option<u64>,     // 実時間の代わりに使う時刻（ムービーの記録・再生用）
+}
+
+impl HuC3Rtc {
+    pub fn new() -> Self {
+        Self {
+            memory: vec![0; 0x100],
+            addr: 0,
+            command: 0,
+            response: 0,
+            base: system_secs(),
+            clock: None,
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.unwrap_or_else(system_secs)
+    }
+
+    // 以後は実時間の代わりに指定の時刻を使う
+    pub fn set_clock(&mut self, secs: u64) {
+        self.clock = Some(secs);
+    }
+
+    // 指定の時刻に電源を入れた状態にする
+    pub fn start_clock(&mut self, secs: u64) {
+        self.clock = Some(secs);
+        self.base = secs;
+    }
+
+    // 応答の読み込み、上位4bitはコマンド
+    pub fn read(&self) -> u8 {
+        (self.command << 4) | self.response
+    }
+
+    // コマンドの書き込み、上位4bitがコマンドで下位4bitが引数
+    pub fn write(&mut self, val: u8) {
+        let arg = val & 0xF;
+        self.command = (val >> 4) & 0x7;
+        match self.command {
+            0x1 => {                                    // 読み込み
+                self.response = self.memory[self.addr];
+                self.addr = (self.addr + 1) & 0xFF;
+            },
+            0x3 => {                                    // 書き込み
+                self.memory[self.addr] = arg;
+                self.addr = (self.addr + 1) & 0xFF;
+            },
+            0x4 => self.addr = (self.addr & 0xF0) | arg as usize,
+            0x5 => self.addr = (self.addr & 0x0F) | (arg as usize) << 4,
+            0x6 => match arg {                          // 拡張コマンド
+                0x0 => self.latch(),
+                0x1 => self.set_time(),
+                0x2 => self.response = 0x1,             // 状態確認
+                _   => {},
+            },
+            _   => {},
+        }
+    }
+
+    // 現在時刻をメモリへ転送
+    fn latch(&mut self) {
+        let elapsed = self.now().saturating_sub(self.base) / SECS_PER_MINUTE;
+        let minutes = elapsed % MINUTES_PER_DAY;
+        let days = elapsed / MINUTES_PER_DAY;
+        for i in 0..3 {
+            self.memory[i] = ((minutes >> (i * 4)) & 0xF) as u8;
+            self.memory[3 + i] = ((days >> (i * 4)) & 0xF) as u8;
+        }
+    }
+
+    // メモリの値を現在時刻にする
+    fn set_time(&mut self) {
+        let nibbles = |start: usize| (0..3).fold(0, |acc, i| acc | (self.memory[start + i] as u64) << (i * 4));
+        let minutes = nibbles(0) + nibbles(3) * MINUTES_PER_DAY;
+        self.base = self.now().saturating_sub(minutes * SECS_PER_MINUTE);
+    }
+}
+
+impl Snapshot for HuC3Rtc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.memory);
+        w.usize(self.addr);
+        w.u8(self.command);
+        w.u8(self.response);
+        w.u64(self.base);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into(&mut self.memory)?;
+        self.addr = r.usize()? & 0xFF;
+        self.command = r.u8()?;
+        self.response = r.u8()?;
+        self.base = r.u64()?;
+        Ok(())
+    }
+}
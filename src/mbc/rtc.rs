@@ -0,0 +1,146 @@
+// MBC3のRTC
+// 0x08-0x0C をSRAMバンクに選択するとレジスタが見える
+
+use std::{
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// 秒、分、時、日（下位8bit）、日（上位1bit）・停止・桁あふれ
+const REG_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const HALT: u8 = 1 << 6;
+const DAY_CARRY: u8 = 1 << 7;
+
+// .savの末尾に付けるRTCデータのサイズ
+pub const TRAILER_SIZE: usize = 48;
+const TRAILER_SIZE_32BIT: usize = 44;       // タイムスタンプが32bitの古い形式
+
+// 実時間（UNIX時間[s]）
+pub fn system_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+pub struct Mbc3Rtc {
+    regs: [u8; 5],
+    latched: [u8; 5],
+    latch_ready: bool,      // 0を書き込むと次の1でラッチする
+    last: u64,              // regsを最後に更新したUNIX時間[s]
+    clock: Option<u64>,     // 実時間の代わりに使う時刻（ムービーの記録・再生用）
+}
+
+impl Mbc3Rtc {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 5],
+            latched: [0; 5],
+            latch_ready: false,
+            last: system_secs(),
+            clock: None,
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.unwrap_or_else(system_secs)
+    }
+
+    // 以後は実時間の代わりに指定の時刻を使う
+    pub fn set_clock(&mut self, secs: u64) {
+        self.clock = Some(secs);
+    }
+
+    // 指定の時刻に電源を入れた状態にする
+    pub fn start_clock(&mut self, secs: u64) {
+        self.clock = Some(secs);
+        self.last = secs;
+    }
+
+    // レジスタの読み込み、ラッチした値が読める
+    pub fn read(&self, reg: usize) -> u8 {
+        self.latched[reg - 0x08]
+    }
+
+    // レジスタの書き込み
+    pub fn write(&mut self, reg: usize, val: u8) {
+        self.update();
+        self.regs[reg - 0x08] = val & REG_MASKS[reg - 0x08];
+    }
+
+    // 0x6000-0x7FFF への書き込み、0→1でラッチ
+    pub fn latch(&mut self, val: u8) {
+        if self.latch_ready && val == 1 {
+            self.update();
+            self.latched = self.regs;
+        }
+        self.latch_ready = val == 0;
+    }
+
+    // 前回の更新からの経過時間を進める
+    fn update(&mut self) {
+        let now = self.now();
+        let elapsed = now.saturating_sub(self.last);
+        self.last = now;
+        if self.regs[4] & HALT > 0 || elapsed == 0 {
+            return;
+        }
+        let days = (((self.regs[4] & 1) as u64) << 8) | self.regs[3] as u64;
+        let total = self.regs[0] as u64
+            + self.regs[1] as u64 * 60
+            + self.regs[2] as u64 * 3600
+            + days * 86400
+            + elapsed;
+        let days = total / 86400;
+        self.regs[0] = (total % 60) as u8;
+        self.regs[1] = (total / 60 % 60) as u8;
+        self.regs[2] = (total / 3600 % 24) as u8;
+        self.regs[3] = days as u8;
+        self.regs[4] = (self.regs[4] & (HALT | DAY_CARRY)) | ((days >> 8) & 1) as u8;
+        if days >= 512 {
+            self.regs[4] |= DAY_CARRY;
+        }
+    }
+
+    // .savの末尾に付けるデータ
+    // 現在値と、ラッチされた値をそれぞれ4byteずつ、最後に64bitのUNIX時間（全てリトルエンディアン）
+    pub fn to_trailer(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(TRAILER_SIZE);
+        for reg in self.regs.iter().chain(self.latched.iter()) {
+            ret.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        ret.extend_from_slice(&self.last.to_le_bytes());
+        ret
+    }
+
+    // .savの末尾のデータから復元する、保存後の経過時間も進める
+    pub fn load_trailer(&mut self, data: &[u8]) -> bool {
+        let last = match data.len() {
+            TRAILER_SIZE       => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            TRAILER_SIZE_32BIT => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _                  => return false,
+        };
+        for i in 0..5 {
+            self.regs[i] = data[i * 4] & REG_MASKS[i];
+            self.latched[i] = data[20 + i * 4] & REG_MASKS[i];
+        }
+        self.last = last;
+        self.update();
+        true
+    }
+}
+
+// .savと同じ形式に、ラッチ待ちの状態を加える
+impl Snapshot for Mbc3Rtc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.to_trailer());
+        w.bool(self.latch_ready);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        if !self.load_trailer(r.bytes()?) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid rtc state"));
+        }
+        self.latch_ready = r.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+// ゲームボーイの機種
+// ブートROMを飛ばして 0x0100 から始める場合は、機種ごとの起動後の状態を再現する
+// ゲームはAレジスタなどの値で機種を判別するため、値は Pan Docs の表と一致させる
+#![allow(dead_code)]
+
+use crate::{
+    cartridge::{CartridgeHeader, CgbSupport},
+    registers::Registers,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg0,   // 初期のDMG
+    Dmg,
+    Mgb,    // ゲームボーイポケット
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,    // ゲームボーイアドバンス
+}
+
+impl Model {
+    // コマンドラインの機種名
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Self::Dmg0),
+            "dmg"  => Some(Self::Dmg),
+            "mgb"  => Some(Self::Mgb),
+            "sgb"  => Some(Self::Sgb),
+            "sgb2" => Some(Self::Sgb2),
+            "cgb"  => Some(Self::Cgb),
+            "agb"  => Some(Self::Agb),
+            _      => None,
+        }
+    }
+
+    // ヘッダから機種を選ぶ、CGB対応ならCGB、SGB対応ならSGB
+    pub fn detect(header: &CartridgeHeader) -> Self {
+        if header.cgb_support() != CgbSupport::None {
+            Self::Cgb
+        } else if header.sgb_support() {
+            Self::Sgb
+        } else {
+            Self::Dmg
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Self::Dmg0 => "DMG0",
+            Self::Dmg  => "DMG",
+            Self::Mgb  => "MGB",
+            Self::Sgb  => "SGB",
+            Self::Sgb2 => "SGB2",
+            Self::Cgb  => "CGB",
+            Self::Agb  => "AGB",
+        }
+    }
+
+    // カラー対応の機種か
+    pub fn is_cgb(&self) -> bool {
+        matches!(*self, Self::Cgb | Self::Agb)
+    }
+
+    // スーパーゲームボーイか
+    pub fn is_sgb(&self) -> bool {
+        matches!(*self, Self::Sgb | Self::Sgb2)
+    }
+
+    // CGBモードで動かすか、CGBでもCGB非対応のソフトはDMG互換モード
+    pub fn cgb_mode(&self, header: &CartridgeHeader) -> bool {
+        self.is_cgb() && header.cgb_support() != CgbSupport::None
+    }
+
+    // OAM破壊バグ（OAMスキャン中の16bitインクリメント・デクリメント）があるか
+    pub fn has_oam_bug(&self) -> bool {
+        !self.is_cgb()
+    }
+
+    // 起動後のCPUレジスタ、cgb_mode はカートリッジがCGBに対応しているか
+    pub fn post_boot_registers(&self, header: &CartridgeHeader, cgb_mode: bool) -> Registers {
+        let mut regs = Registers {
+            sp: 0xFFFE,
+            pc: 0x0100,
+            ..Registers::default()
+        };
+        // DMG・MGBのH・Cフラグはヘッダチェックサムが0でなければ立つ
+        let checksum_flags = if header.header_checksum() == 0 { 0x80 } else { 0xB0 };
+        let (a, f, b, c, d, e, h, l) = match *self {
+            Self::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Self::Dmg  => (0x01, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Self::Mgb  => (0xFF, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Self::Sgb  => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Self::Sgb2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Self::Cgb | Self::Agb => if cgb_mode {
+                (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D)
+            } else {
+                // DMG互換モードでは任天堂のソフトならBはタイトルの合計、HLはBの値で変わる
+                let b = if header.licensee_code() == "01" {
+                    header.title_checksum()
+                } else {
+                    0x00
+                };
+                let (h, l) = if b == 0x43 || b == 0x58 { (0x99, 0x1A) } else { (0x00, 0x7C) };
+                (0x11, 0x80, b, 0x00, 0x00, 0x08, h, l)
+            },
+        };
+        regs.a = a;
+        regs.f = f;
+        regs.b = b;
+        regs.c = c;
+        regs.d = d;
+        regs.e = e;
+        regs.h = h;
+        regs.l = l;
+        // AGBのブートROMは最後に INC B を実行する
+        if *self == Self::Agb {
+            regs.b = regs.b.wrapping_add(1);
+            regs.f = (if regs.b == 0 { 0x80 } else { 0x00 }) | (if regs.b & 0x0F == 0 { 0x20 } else { 0x00 }) | (regs.f & 0x10);
+        }
+        regs
+    }
+
+    // 起動後のDIVの上位8bit（内部カウンタの位相）
+    // SGBとCGBは起動にかかる時間で変わるため、代表的な値
+    pub fn post_boot_div(&self) -> u8 {
+        match *self {
+            Self::Dmg0 => 0x18,
+            Self::Dmg | Self::Mgb => 0xAB,
+            Self::Sgb | Self::Sgb2 => 0x00,
+            Self::Cgb | Self::Agb => 0x00,
+        }
+    }
+
+    // 起動後のI/Oレジスタ（アドレス、値）、書き込んだ後に読むと起動後の値になる
+    // DIVは書き込むと0になるため post_boot_div で設定する
+    // サウンドは電源を先に入れる、NR14の発音開始でNR52のチャンネル1が発音中になる（SGBは起動音が無い）
+    pub fn post_boot_io(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0xFF00, 0xCF),     // P1
+            (0xFF01, 0x00),     // SB
+            (0xFF02, if self.is_cgb() { 0x7F } else { 0x7E }),  // SC
+            (0xFF05, 0x00),     // TIMA
+            (0xFF06, 0x00),     // TMA
+            (0xFF07, 0xF8),     // TAC
+            (0xFF0F, 0xE1),     // IF
+            (0xFF26, 0x80),     // NR52
+            (0xFF10, 0x80),     // NR10
+            (0xFF11, 0xBF),     // NR11
+            (0xFF12, 0xF3),     // NR12
+            (0xFF13, 0xFF),     // NR13
+            (0xFF14, if self.is_sgb() { 0x3F } else { 0xBF }),  // NR14
+            (0xFF16, 0x3F),     // NR21
+            (0xFF17, 0x00),     // NR22
+            (0xFF18, 0xFF),     // NR23
+            (0xFF19, 0xBF),     // NR24
+            (0xFF1A, 0x7F),     // NR30
+            (0xFF1B, 0xFF),     // NR31
+            (0xFF1C, 0x9F),     // NR32
+            (0xFF1D, 0xFF),     // NR33
+            (0xFF1E, 0xBF),     // NR34
+            (0xFF20, 0xFF),     // NR41
+            (0xFF21, 0x00),     // NR42
+            (0xFF22, 0x00),     // NR43
+            (0xFF23, 0xBF),     // NR44
+            (0xFF24, 0x77),     // NR50
+            (0xFF25, 0xF3),     // NR51
+            (0xFF40, 0x91),     // LCDC
+            (0xFF41, if *self == Self::Dmg0 { 0x81 } else { 0x85 }),    // STAT
+            (0xFF42, 0x00),     // SCY
+            (0xFF43, 0x00),     // SCX
+            (0xFF45, 0x00),     // LYC
+            (0xFF47, 0xFC),     // BGP
+            (0xFF4A, 0x00),     // WY
+            (0xFF4B, 0x00),     // WX
+            (0xFF50, 0x01),     // ブートROMを無効に
+            (0xFFFF, 0x00),     // IE
+        ]
+    }
+}
@@ -0,0 +1,252 @@
+// 入力ムービー
+// フレーム毎のボタンの状態を記録・再生する
+// ヘッダ：マジック、形式のバージョン、ROMのSHA-1、ブートROMのSHA-1、機種、ブートROMを飛ばしたか、
+// カメラの画像（ファイル名かテストパターン名）、開始時刻、やり直し回数
+// 続いて開始点（電源投入か、埋め込んだステート）とフレーム毎の入力が並ぶ
+// RTCは実時間ではなく開始時刻から経過したMサイクルで進める
+// チートの切り替えは記録しないため、記録・再生中はチートを使わない
+
+use std::{fs, io, path::Path};
+
+use sha1_smol::Sha1;
+
+use crate::{
+    console::Console,
+    mbc,
+    model::Model,
+    state::{self, StateReader, StateWriter},
+};
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const MOVIE_VERSION: u16 = 2;
+const M_CYCLES_PER_SEC: u64 = 1 << 20;
+
+pub struct Movie {
+    rom_hash: [u8; 20],
+    bootrom_hash: [u8; 20],
+    model: Model,
+    skip_boot: bool,
+    camera: Option<String>,
+    start_time: u64,            // 開始時のRTCの時刻（UNIX時間[s]）
+    rerecords: u32,             // ステートの読み込みや巻き戻しでやり直した回数
+    state: Option<Vec<u8>>,     // Noneなら電源投入から
+    inputs: Vec<u8>,            // フレーム毎のボタン
+    start_frame: u64,           // 開始時の本体のフレーム数・Mサイクル数
+    start_cycles: u64,
+}
+
+impl Movie {
+    // 記録用、開始時刻は現在時刻
+    pub fn new(console: &Console, skip_boot: bool, camera: Option<String>, state: Option<Vec<u8>>) -> Self {
+        Self {
+            rom_hash: Sha1::from(console.peripherals.cartridge.rom()).digest().bytes(),
+            bootrom_hash: console.peripherals.bootrom().hash(),
+            model: console.model(),
+            skip_boot,
+            camera,
+            start_time: mbc::system_secs(),
+            rerecords: 0,
+            state,
+            inputs: Vec::new(),
+            start_frame: 0,
+            start_cycles: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let mut r = StateReader::new(&data);
+        if r.raw(MAGIC.len())? != MAGIC {
+            return Err(state::invalid("not a movie file"));
+        }
+        let version = r.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(state::invalid(format!("unsupported movie version {}", version)));
+        }
+        let rom_hash = r.raw(20)?.try_into().unwrap();
+        let bootrom_hash = r.raw(20)?.try_into().unwrap();
+        let model = std::str::from_utf8(r.bytes()?)
+            .ok()
+            .and_then(Model::from_name)
+            .ok_or_else(|| state::invalid("unknown model"))?;
+        let skip_boot = r.bool()?;
+        let camera = String::from_utf8(r.bytes()?.to_vec()).map_err(|_| state::invalid("invalid camera source"))?;
+        let camera = (!camera.is_empty()).then_some(camera);
+        let start_time = r.u64()?;
+        let rerecords = r.u32()?;
+        let has_state = r.bool()?;
+        let state = r.bytes()?;
+        let state = has_state.then(|| state.to_vec());
+        let inputs = r.bytes()?.to_vec();
+        Ok(Self {
+            rom_hash,
+            bootrom_hash,
+            model,
+            skip_boot,
+            camera,
+            start_time,
+            rerecords,
+            state,
+            inputs,
+            start_frame: 0,
+            start_cycles: 0,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = StateWriter::default();
+        w.raw(MAGIC);
+        w.u16(MOVIE_VERSION);
+        w.raw(&self.rom_hash);
+        w.raw(&self.bootrom_hash);
+        w.bytes(self.model.name().as_bytes());
+        w.bool(self.skip_boot);
+        w.bytes(self.camera.as_deref().unwrap_or_default().as_bytes());
+        w.u64(self.start_time);
+        w.u32(self.rerecords);
+        w.bool(self.state.is_some());
+        w.bytes(self.state.as_deref().unwrap_or_default());
+        w.bytes(&self.inputs);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, w.finish())?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        Sha1::from(rom).digest().bytes() == self.rom_hash
+    }
+
+    pub fn matches_bootrom(&self, console: &Console) -> bool {
+        console.peripherals.bootrom().hash() == self.bootrom_hash
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn skip_boot(&self) -> bool {
+        self.skip_boot
+    }
+
+    pub fn camera(&self) -> Option<&str> {
+        self.camera.as_deref()
+    }
+
+    pub fn rerecords(&self) -> u32 {
+        self.rerecords
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // 開始点に合わせる、ステートから始まる場合は読み込む
+    // RTCは開始時刻から動かす、電源投入からの場合はその時刻に電源を入れたことにする
+    pub fn begin(&mut self, console: &mut Console) -> io::Result<()> {
+        match self.state {
+            Some(ref state) => {
+                state::load(console, state)?;
+                console.set_clock(self.start_time);
+            },
+            None => console.start_clock(self.start_time),
+        }
+        self.start_frame = console.frames();
+        self.start_cycles = console.cycles();
+        Ok(())
+    }
+
+    // RTCの時刻を経過したMサイクルから決める、フレームの終わりやステートの読み込み後に呼ぶ
+    pub fn sync_clock(&self, console: &mut Console) {
+        let elapsed = console.cycles().saturating_sub(self.start_cycles) / M_CYCLES_PER_SEC;
+        console.set_clock(self.start_time + elapsed);
+    }
+
+    // 終わったフレームの入力を記録する
+    // ステートの読み込みや巻き戻しで戻った場合は、それ以降の記録を捨てて上書きする
+    pub fn record_frame(&mut self, console: &Console) {
+        let Some(index) = console.frames().checked_sub(self.start_frame + 1) else {
+            return;
+        };
+        self.inputs.truncate(index as usize);
+        self.inputs.push(console.buttons());
+    }
+
+    // 次のフレームの入力、記録の終わりを過ぎていればNone
+    pub fn input(&self, console: &Console) -> Option<u8> {
+        let index = console.frames().checked_sub(self.start_frame)?;
+        self.inputs.get(index as usize).copied()
+    }
+
+    pub fn rerecord(&mut self) {
+        self.rerecords += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bootrom::Bootrom, cartridge::{Cartridge, NINTENDO_LOGO}, joypad};
+
+    // RTCの秒と十字キーをWRAMに書き続けるMBC3+RTCのROM
+    fn rtc_console() -> Console {
+        let prog = [
+            0x3E, 0x0A, 0xEA, 0x00, 0x10,   // LD A,0x0A; LD (0x1000),A     RAM・RTCを有効にする
+            0x3E, 0x08, 0xEA, 0x00, 0x40,   // LD A,0x08; LD (0x4000),A     RTCの秒を選ぶ
+            0x3E, 0x00, 0xEA, 0x00, 0x60,   // LD A,0x00; LD (0x6000),A     ラッチ
+            0x3E, 0x01, 0xEA, 0x00, 0x60,   // LD A,0x01; LD (0x6000),A
+            0x11, 0x00, 0xA0, 0x1A,         // LD DE,0xA000; LD A,(DE)
+            0xEA, 0x00, 0xC0,               // LD (0xC000),A
+            0x3E, 0x20, 0xE0, 0x00,         // LD A,0x20; LDH (0x00),A      十字キーを選ぶ
+            0x11, 0x00, 0xFF, 0x1A,         // LD DE,0xFF00; LD A,(DE)
+            0xEA, 0x01, 0xC0,               // LD (0xC001),A
+            0x18, 0xE2,                     // JR 0x015A
+        ];
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x147] = 0x10;      // MBC3+TIMER+RAM+BATTERY
+        rom[0x149] = 0x02;      // 8KB
+        rom[0x150..0x150 + prog.len()].copy_from_slice(&prog);
+        let cartridge = Cartridge::from_bytes(rom, false).unwrap();
+        let mut console = Console::new(Model::Dmg, Bootrom::new(), cartridge);
+        console.skip_boot();
+        console
+    }
+
+    // 記録したムービーを再生すると、RTCも含めて記録時と同じ状態になる
+    #[test]
+    fn replay_matches_recording() {
+        let mut console = rtc_console();
+        let mut movie = Movie::new(&console, true, None, None);
+        movie.begin(&mut console).unwrap();
+        for frame in 0..300u32 {
+            console.set_buttons(if frame % 7 < 3 { joypad::RIGHT } else { joypad::UP });
+            console.run_frame();
+            movie.record_frame(&console);
+            movie.sync_clock(&mut console);
+        }
+        let recorded = state::save(&console);
+        // 5秒ほど進んでいる
+        assert!(console.peripherals.read(&console.cpu.interrupts, 0xC000) >= 4);
+
+        let path = std::env::temp_dir().join(format!("gb-movie-test-{}.gbm", std::process::id()));
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+        let mut movie = loaded.unwrap();
+        assert_eq!(movie.len(), 300);
+
+        let mut console = rtc_console();
+        movie.begin(&mut console).unwrap();
+        while let Some(buttons) = movie.input(&console) {
+            console.set_buttons(buttons);
+            console.run_frame();
+            movie.sync_clock(&mut console);
+        }
+        assert_eq!(state::save(&console), recorded);
+    }
+}
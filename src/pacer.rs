@@ -0,0 +1,62 @@
+// フレームの表示間隔の調整
+// 1フレームは 70224クロック / 4194304Hz（約59.73Hz）、早送り・スローでは速さを掛ける
+// 開始からのフレーム数で目標時刻を決めるため、1フレーム毎の誤差は積み重ならない
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{console::CYCLES_PER_FRAME, CPU_CLOCK_HZ, M_CYCLE_CLOCK};
+
+// これ以上遅れたら追いつこうとせず、基準の時刻をやり直す
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+pub struct Pacer {
+    speed: f64,         // 1.0で実機と同じ、0なら待たない
+    start: Instant,
+    frames: u64,        // start からのフレーム数
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self {
+            speed: 1.0,
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    // 速さを変える、変わった場合は基準をやり直す
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed != self.speed {
+            self.speed = speed;
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.start = Instant::now();
+        self.frames = 0;
+    }
+
+    // フレームの終わりに呼び、目標の時刻まで待つ
+    pub fn end_frame(&mut self) {
+        if self.speed <= 0.0 {
+            return;
+        }
+        self.frames += 1;
+        let target = Duration::from_secs_f64(self.frames as f64 * frame_secs() / self.speed);
+        let elapsed = self.start.elapsed();
+        if let Some(wait) = target.checked_sub(elapsed) {
+            thread::sleep(wait);
+        } else if elapsed - target > MAX_LAG {
+            self.reset();
+        }
+    }
+}
+
+// 1フレームの時間[s]
+fn frame_secs() -> f64 {
+    (CYCLES_PER_FRAME as u128 * M_CYCLE_CLOCK) as f64 / CPU_CLOCK_HZ as f64
+}
@@ -0,0 +1,245 @@
+// IPS・UPS・BPSパッチの適用
+// ROMファイル自体は書き換えず、読み込んだデータにのみ適用する
+
+use std::{
+    error, fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,                  // パッチが途中で終わっている
+    OutOfRange,                 // 範囲外への読み書き
+    SourceChecksum,             // 適用先のROMが違う
+    TargetChecksum,
+    PatchChecksum,              // パッチ自体が壊れている
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::UnknownFormat  => "unknown patch format",
+            Self::Truncated      => "patch is truncated",
+            Self::OutOfRange     => "patch accesses out of range",
+            Self::SourceChecksum => "source rom crc mismatch",
+            Self::TargetChecksum => "patched rom crc mismatch",
+            Self::PatchChecksum  => "patch crc mismatch",
+        })
+    }
+}
+
+impl error::Error for PatchError {}
+
+// ROMと同じ名前のパッチファイルを探す
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    ["ips", "ups", "bps"]
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+// 先頭のマジックで形式を判定して適用する
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// パッチの読み出し位置
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let ret = *self.data.get(self.pos).ok_or(PatchError::Truncated)?;
+        self.pos += 1;
+        Ok(ret)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], PatchError> {
+        let ret = self.data.get(self.pos..self.pos + len).ok_or(PatchError::Truncated)?;
+        self.pos += len;
+        Ok(ret)
+    }
+
+    // ビッグエンディアン
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self.bytes(len)?.iter().fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+
+    // UPS・BPSの可変長整数
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let (mut val, mut shift) = (0usize, 1usize);
+        loop {
+            let x = self.byte()?;
+            val = ((x & 0x7F) as usize).checked_mul(shift)
+                .and_then(|v| val.checked_add(v))
+                .ok_or(PatchError::OutOfRange)?;
+            if x & 0x80 > 0 {
+                return Ok(val);
+            }
+            // 7bitずつ桁が上がる、usizeを超える値は壊れたパッチ
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfRange)?;
+            val = val.checked_add(shift).ok_or(PatchError::OutOfRange)?;
+        }
+    }
+}
+
+// IPS：オフセット（3byte）とサイズ（2byte）のレコードが "EOF" まで並ぶ
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut out = rom.to_vec();
+    let mut r = Reader { data: patch, pos: 5 };
+    loop {
+        if r.data.get(r.pos..r.pos + 3) == Some(b"EOF") {
+            r.pos += 3;
+            break;
+        }
+        let offset = r.be(3)?;
+        let size = r.be(2)?;
+        // サイズが0の場合はRLE
+        let data = if size == 0 {
+            let len = r.be(2)?;
+            vec![r.byte()?; len]
+        } else {
+            r.bytes(size)?.to_vec()
+        };
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // EOFの後ろに3byteあれば切り詰め後のサイズ
+    if let Ok(len) = r.be(3) {
+        out.truncate(len);
+    }
+    Ok(out)
+}
+
+// 末尾12byteのCRC（適用前、適用後、パッチ自身）
+fn footer(patch: &[u8]) -> Result<(u32, u32), PatchError> {
+    if patch.len() < 16 {
+        return Err(PatchError::Truncated);
+    }
+    let crc = |pos: usize| u32::from_le_bytes(patch[pos..pos + 4].try_into().unwrap());
+    let end = patch.len() - 12;
+    if crc32fast::hash(&patch[..end + 8]) != crc(end + 8) {
+        return Err(PatchError::PatchChecksum);
+    }
+    Ok((crc(end), crc(end + 4)))
+}
+
+// UPS：差分をXORで適用する
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (source_crc, target_crc) = footer(patch)?;
+    if crc32fast::hash(rom) != source_crc {
+        return Err(PatchError::SourceChecksum);
+    }
+    let end = patch.len() - 12;
+    let mut r = Reader { data: &patch[..end], pos: 4 };
+    let _source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+
+    let mut pos = 0;
+    while r.pos < end {
+        pos += r.varint()?;
+        // 0が来るまでXORする
+        loop {
+            let x = r.byte()?;
+            if x == 0 {
+                pos += 1;
+                break;
+            }
+            if let Some(b) = out.get_mut(pos) {
+                *b ^= x;
+            }
+            pos += 1;
+        }
+    }
+    if crc32fast::hash(&out) != target_crc {
+        return Err(PatchError::TargetChecksum);
+    }
+    Ok(out)
+}
+
+// BPS：適用前・適用後のデータからのコピーと新しいデータの組み合わせ
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (source_crc, target_crc) = footer(patch)?;
+    if crc32fast::hash(rom) != source_crc {
+        return Err(PatchError::SourceChecksum);
+    }
+    let end = patch.len() - 12;
+    let mut r = Reader { data: &patch[..end], pos: 4 };
+    let _source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0isize, 0isize);
+    // 相対オフセット、最下位bitが符号
+    let relative = |r: &mut Reader, offset: &mut isize| -> Result<usize, PatchError> {
+        let data = r.varint()?;
+        let delta = (data >> 1) as isize;
+        *offset += if data & 1 > 0 { -delta } else { delta };
+        usize::try_from(*offset).map_err(|_| PatchError::OutOfRange)
+    };
+
+    while r.pos < end {
+        let data = r.varint()?;
+        let len = (data >> 2) + 1;
+        match data & 0b11 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or(PatchError::OutOfRange)?);
+            },
+            // TargetRead
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // SourceCopy
+            2 => {
+                let start = relative(&mut r, &mut source_offset)?;
+                out.extend_from_slice(rom.get(start..start + len).ok_or(PatchError::OutOfRange)?);
+                source_offset += len as isize;
+            },
+            // TargetCopy、書き込み中の範囲と重なるので1byteずつ
+            _ => {
+                let start = relative(&mut r, &mut target_offset)?;
+                for i in start..start + len {
+                    out.push(*out.get(i).ok_or(PatchError::OutOfRange)?);
+                }
+                target_offset += len as isize;
+            },
+        }
+    }
+    if out.len() != target_size || crc32fast::hash(&out) != target_crc {
+        return Err(PatchError::TargetChecksum);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        let mut r = Reader { data: &[0x80, 0x00, 0x80, 0x7F, 0x01, 0x80], pos: 0 };
+        assert_eq!(r.varint(), Ok(0));
+        assert_eq!(r.varint(), Ok(0x80));
+        assert_eq!(r.varint(), Ok(0x417F));
+        // 上位の桁が続いてusizeを超える
+        let mut r = Reader { data: &[0x7F; 12], pos: 0 };
+        assert_eq!(r.varint(), Err(PatchError::OutOfRange));
+    }
+}
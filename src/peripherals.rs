@@ -1,27 +1,50 @@
 // 周辺機器管理
 #![allow(dead_code)]
 
+use std::io;
+
 use crate::{
-    bootrom::Bootrom, cartridge::Cartridge, cpu::interrupts::Interrupts, hram::HRam, ppu::Ppu
+    apu::Apu, bootrom::Bootrom, cartridge::Cartridge, cpu::interrupts::Interrupts, hram::HRam, infrared::Infrared, joypad::Joypad, model::Model, ppu::Ppu, serial::Serial, sgb::Sgb,
+    state::{Snapshot, StateReader, StateWriter}, timer::Timer, wram::WRam
 };
 
 pub struct Peripherals {
-    cartridge: Cartridge,
+    pub cartridge: Cartridge,
     bootrom: Bootrom,
+    wram: WRam,
     hram: HRam,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub infrared: Infrared,
+    pub timer: Timer,
+    apu: Apu,
     pub ppu: Ppu,
+    pub sgb: Option<Sgb>,   // SGBでSGB対応ソフトの場合のみ
 }
 
 impl Peripherals {
-    pub fn new (bootrom: Bootrom, cartridge: Cartridge) -> Self {
+    pub fn new (bootrom: Bootrom, cartridge: Cartridge, model: Model) -> Self {
+        let cgb_mode = model.cgb_mode(&cartridge.header);
+        let sgb = (model.is_sgb() && cartridge.header.sgb_support()).then(Sgb::new);
         Self {
             cartridge,
             bootrom,
+            wram: WRam::new(),
             hram: HRam::new(),
-            ppu: Ppu::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(cgb_mode),
+            infrared: Infrared::new(cgb_mode),
+            timer: Timer::new(),
+            apu: Apu::new(),
+            ppu: Ppu::new(model, cgb_mode),
+            sgb,
         }
     }
 
+    pub fn bootrom(&self) -> &Bootrom {
+        &self.bootrom
+    }
+
     // MMIO読み込み
     pub fn read(&self, interrupts: &Interrupts, addr: u16) -> u8 {
         // dbg
@@ -29,16 +52,24 @@ impl Peripherals {
         
         match addr {
             // ブートROMが無効の時はカートリッジ
-            0x0000..=0x00FF => if self.bootrom.is_active() {
-                self.bootrom.read(addr)
-            } else {
-                self.cartridge.read(addr)
-            },
-            0x0100..=0x7FFF => self.cartridge.read(addr),
+            0x0000..=0x08FF if self.bootrom.is_mapped(addr) => self.bootrom.read(addr),
+            0x0000..=0x7FFF => self.cartridge.read(addr),
             0xA000..=0xBFFF => self.cartridge.read(addr), 
             0x8000..=0x9FFF => self.ppu.read(addr),
+            0xC000..=0xFDFF => self.wram.read(addr),
             0xFE00..=0xFE9F => self.ppu.read(addr),
-            0xFF40..=0xFF4B => self.ppu.read(addr),
+            0xFF00          => match self.sgb {
+                Some(ref sgb) => {
+                    let val = self.joypad.read(addr);
+                    (val & 0xF0) | sgb.read_p1(self.joypad.select(), val & 0x0F)
+                },
+                None => self.joypad.read(addr),
+            },
+            0xFF01..=0xFF02 => self.serial.read(addr),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF40..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => self.ppu.read(addr),
+            0xFF56          => self.infrared.read(addr),
             0xFF80..=0xFFFE => self.hram.read(addr),
             0xFF0F          => interrupts.read(addr),
             0xFFFF          => interrupts.read(addr),
@@ -51,10 +82,28 @@ impl Peripherals {
         match addr {
             0xFF50          => self.bootrom.write(addr, val),
             0x0100..=0x7FFF => self.cartridge.write(addr, val),
-            0xA000..=0xBFFF => self.cartridge.write(addr, val), 
+            0xA000..=0xBFFF => {
+                self.cartridge.write(addr, val);
+                // HuC1・HuC3の赤外線LED
+                if let Some(on) = self.cartridge.ir_led() {
+                    self.infrared.set_cart_led(on);
+                }
+            },
             0x8000..=0x9FFF => self.ppu.write(addr, val),
+            0xC000..=0xFDFF => self.wram.write(addr, val),
             0xFE00..=0xFE9F => self.ppu.write(addr, val),
-            0xFF40..=0xFF4B => self.ppu.write(addr, val),
+            0xFF00          => {
+                // SGBはP1のパルスでコマンドを受け取る
+                if let Some(ref mut sgb) = self.sgb {
+                    sgb.write_p1(self.joypad.select(), val);
+                }
+                self.joypad.write(addr, val);
+            },
+            0xFF01..=0xFF02 => self.serial.write(addr, val),
+            0xFF04..=0xFF07 => self.timer.write(interrupts, addr, val),
+            0xFF10..=0xFF3F => self.apu.write(addr, val),
+            0xFF40..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => self.ppu.write(addr, val),
+            0xFF56          => self.infrared.write(addr, val),
             0xFF80..=0xFFFE => self.hram.write(addr, val),
             0xFF0F          => interrupts.write(addr, val),
             0xFFFF          => interrupts.write(addr, val),
@@ -63,3 +112,37 @@ impl Peripherals {
     }
 }
 
+impl Snapshot for Peripherals {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.cartridge.save_state(w);
+        self.bootrom.save_state(w);
+        self.wram.save_state(w);
+        self.hram.save_state(w);
+        self.joypad.save_state(w);
+        self.serial.save_state(w);
+        self.infrared.save_state(w);
+        self.timer.save_state(w);
+        self.apu.save_state(w);
+        self.ppu.save_state(w);
+        if let Some(ref sgb) = self.sgb {
+            sgb.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.cartridge.load_state(r)?;
+        self.bootrom.load_state(r)?;
+        self.wram.load_state(r)?;
+        self.hram.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.infrared.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.ppu.load_state(r)?;
+        if let Some(ref mut sgb) = self.sgb {
+            sgb.load_state(r)?;
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,8 @@ enum Mode {
 }
 
 pub struct Ppu {
+    model: Model,
+    cgb_mode: bool,     // CGBモード（CGBでCGB非対応ソフトの場合はDMG互換モード）
     mode: Mode,
     lcdc: u8,
     stat: u8,
@@ -22,13 +24,25 @@ pub struct Ppu {
     wy: u8,
     wx: u8,
     cycles: u8,
+    vram_bank: u8,          // VBK（CGBモードのみ）
+    bcps: u8,               // 背景パレットの指定（bit7で書き込み毎に自動インクリメント）
+    ocps: u8,               // スプライトパレットの指定
+    bg_palettes: [u8; 64],  // 背景パレット8個分、RGB555のリトルエンディアン
+    obj_palettes: [u8; 64],
     vram: Vec<u8>,
     oam: Vec<u8>,
     pub buffer: Vec<u8>,
+    pub shades: Vec<u8>,    // パレット適用後の色番号（0～3）、SGBの色付け用
+    colors: Vec<u16>,       // RGB555の画面、CGBとAGBのみ
 }
 
 
+use std::io;
+
 use crate::{
+    image::{luma, rgb888},
+    model::Model,
+    state::{Snapshot, StateReader, StateWriter},
     LCD_WIDTH,
     LCD_PIXELS,
 };
@@ -51,10 +65,15 @@ const VBLANK_INT: u8 = 1 << 4;
 const HBLANK_INT: u8 = 1 << 3;
 const LYC_EQ_LY: u8 = 1 << 2;
 
+// CGBのブートROMがDMG用ソフトに設定するパレット（タイトルで選ばれない場合の緑）
+const COMPAT_PALETTE: [u16; 4] = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+
 
 impl Ppu {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(model: Model, cgb_mode: bool) -> Self {
+        let mut ppu = Self {
+            model,
+            cgb_mode,
             mode: Mode::OamScan,
             lcdc: 0,
             stat: 0,
@@ -68,19 +87,74 @@ impl Ppu {
             wy: 0,
             wx: 0,
             cycles: 20,
-            vram: vec![0; 0x2000],
+            vram_bank: 0,
+            bcps: 0,
+            ocps: 0,
+            bg_palettes: [0; 64],
+            obj_palettes: [0; 64],
+            vram: vec![0; if cgb_mode { 0x4000 } else { 0x2000 }],
             oam: vec![0; 0xA0],
             buffer: vec![0; LCD_PIXELS],
+            shades: vec![0; LCD_PIXELS],
+            colors: vec![0x7FFF; LCD_PIXELS],
+        };
+        // DMG互換モードではBGPなどの色番号を、ブートROMが設定したパレット0で色にする
+        if model.is_cgb() && !cgb_mode {
+            let palette: Vec<u8> = COMPAT_PALETTE.iter().flat_map(|c| c.to_le_bytes()).collect();
+            ppu.bg_palettes[..8].copy_from_slice(&palette);
+            ppu.obj_palettes[..8].copy_from_slice(&palette);
+            ppu.obj_palettes[8..16].copy_from_slice(&palette);
+        }
+        ppu
+    }
+
+    // CGBとAGBはRGB888の画面、それ以外はNone（グレースケールの buffer を使う）
+    pub fn rgb(&self) -> Option<Vec<u8>> {
+        self.model.is_cgb().then(|| self.colors.iter().flat_map(|&c| rgb888(c)).collect())
+    }
+
+    // OAM破壊バグ、OAMスキャン中に 0xFE00～0xFEFF を指す16bitレジスタをINC・DECすると
+    // スキャン中の行（8byte）が直前の行と混ざる
+    pub fn oam_bug(&mut self, addr: u16) {
+        if !self.model.has_oam_bug() || self.mode != Mode::OamScan || self.lcdc & PPU_ENABLE == 0 || !(0xFE00..=0xFEFF).contains(&addr) {
+            return;
+        }
+        // 1Mサイクルで1行ずつ読む、先頭の行は壊れない
+        let row = 20 - self.cycles as usize;
+        if row == 0 || row >= 20 {
+            return;
+        }
+        let (cur, prev) = (row * 8, (row - 1) * 8);
+        let word = |i: usize| u16::from_le_bytes([self.oam[i], self.oam[i + 1]]);
+        let (a, b, c) = (word(cur), word(prev), word(prev + 4));
+        self.oam[cur..cur + 2].copy_from_slice(&(((a ^ c) & (b ^ c)) ^ c).to_le_bytes());
+        self.oam.copy_within(prev + 2..prev + 8, cur + 2);
+    }
+
+    fn vram_offset(&self) -> usize {
+        (self.vram_bank as usize) << 13
+    }
+
+    // パレットの色（RGB555）
+    fn palette_color(palettes: &[u8; 64], pal: usize, color: u8) -> u16 {
+        let i = pal * 8 + color as usize * 2;
+        u16::from_le_bytes([palettes[i], palettes[i + 1]]) & 0x7FFF
+    }
+
+    // BCPD・OCPDの書き込み、指定のbit7が立っていれば次の位置へ進む
+    fn write_palette(palettes: &mut [u8; 64], index: &mut u8, val: u8) {
+        palettes[(*index & 0x3F) as usize] = val;
+        if *index & 0x80 > 0 {
+            *index = 0x80 | (index.wrapping_add(1) & 0x3F);
         }
     }
 
-    
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
             0x8000..=0x9FFF => if self.mode == Mode::Drawing {
                 0xFF
             } else {
-                self.vram[addr as usize & 0x1FFF]
+                self.vram[self.vram_offset() | addr as usize & 0x1FFF]
             },
             0xFE00..=0xFE9F => if self.mode == Mode::Drawing || self.mode == Mode::OamScan {
                 0xFF
@@ -91,13 +165,20 @@ impl Ppu {
             0xFF41 => 0x80 | self.stat | self.mode as u8,
             0xFF42 => self.scy,
             0xFF43 => self.scx,
-            0xFF44 => self.ly,
+            0xFF44 => self.current_ly(),
             0xFF45 => self.lyc,
             0xFF47 => self.bgp,
             0xFF48 => self.obp0,
             0xFF49 => self.obp1,
             0xFF4A => self.wy,
             0xFF4B => self.wx,
+            // CGBの機能はDMG互換モードでは読めない
+            0xFF4F if self.cgb_mode => 0xFE | self.vram_bank,
+            0xFF68 if self.cgb_mode => 0x40 | self.bcps,
+            0xFF69 if self.cgb_mode => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A if self.cgb_mode => 0x40 | self.ocps,
+            0xFF6B if self.cgb_mode => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            0xFF4F | 0xFF68..=0xFF6B => 0xFF,
             _ => panic!(""),
         }
     }
@@ -107,7 +188,8 @@ impl Ppu {
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
             0x8000..=0x9FFF => if self.mode != Mode::Drawing {
-                self.vram[addr as usize & 0x1FFF] = val;
+                let offset = self.vram_offset();
+                self.vram[offset | addr as usize & 0x1FFF] = val;
             },
             0xFE00..=0xFE9F => if self.mode != Mode::Drawing || self.mode != Mode::OamScan {
                 self.oam[addr as usize & 0xFF] = val;
@@ -123,6 +205,12 @@ impl Ppu {
             0xFF49 => self.obp1 = val,
             0xFF4A => self.wy = val,
             0xFF4B => self.wx = val,
+            0xFF4F if self.cgb_mode => self.vram_bank = val & 0x01,
+            0xFF68 if self.cgb_mode => self.bcps = val & 0xBF,
+            0xFF69 if self.cgb_mode => Self::write_palette(&mut self.bg_palettes, &mut self.bcps, val),
+            0xFF6A if self.cgb_mode => self.ocps = val & 0xBF,
+            0xFF6B if self.cgb_mode => Self::write_palette(&mut self.obj_palettes, &mut self.ocps, val),
+            0xFF4F | 0xFF68..=0xFF6B => (),
             _ => panic!("ppu write {:x}", addr),
 
         }
@@ -134,16 +222,16 @@ impl Ppu {
         let r = (row * 2) as usize;     // タイルは1行（8pix）あたり16bit
         let c = (7 - col) as usize;     // col列目は（7-col）bit目
         let tile_addr = tile_idx << 4;  // タイルの開始アドレスはタイルのインデックスの16倍
-        let low = self.vram[(tile_addr | r) & 0x1FFF];             // ピクセルの上位bit（8pix分）
-        let high = self.vram[(tile_addr | (r + 1)) & 0x1FFF];      // ピクセルの下位bit（8pix分）
+        let mask = self.vram.len() - 1;                             // CGBはバンク1のタイルも使う
+        let low = self.vram[(tile_addr | r) & mask];               // ピクセルの上位bit（8pix分）
+        let high = self.vram[(tile_addr | (r + 1)) & mask];        // ピクセルの下位bit（8pix分）
         (((high >> c) & 1) << 1) | ((low >> c) & 1)                    // ピクセルの値
 
     }
 
     // タイルマップの特定のマスに格納されたタイルのインデックスを取得する
     fn get_tile_idx_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> usize{
-        let start_addr: usize = 0x1800 | ((tile_map as usize) << 10);
-        let ret = self.vram[start_addr | (((row as usize) << 5) + col as usize) & 0x3FF];
+        let ret = self.vram[Self::tile_map_addr(tile_map, row, col)];
         // LCDCのアドレス指定モードに応じて変更
         if self.lcdc & TILE_DATA_ADDRESSING_MODE > 0 {
             ret as usize
@@ -152,8 +240,29 @@ impl Ppu {
         }
     }
 
+    // タイルマップのマスのアドレス、CGBではバンク1の同じアドレスが属性
+    fn tile_map_addr(tile_map: bool, row: u8, col: u8) -> usize {
+        let start_addr: usize = 0x1800 | ((tile_map as usize) << 10);
+        start_addr | (((row as usize) << 5) + col as usize) & 0x3FF
+    }
+
+    // 画面に表示されているタイルのデータ（SGBのVRAM転送用、4KB）
+    // 背景マップの左上から1行20タイルずつ並べた256タイル分
+    pub fn screen_tile_data(&self) -> Vec<u8> {
+        (0..256)
+            .flat_map(|i| {
+                let tile_idx = self.get_tile_idx_from_tile_map(self.lcdc & BG_TILE_MAP > 0, (i / 20) as u8, (i % 20) as u8);
+                self.vram[tile_idx << 4..(tile_idx << 4) + 16].to_vec()
+            })
+            .collect()
+    }
+
     // bgのレンダリング
     fn render_bg (&mut self) {
+        if self.cgb_mode {
+            self.render_bg_cgb();
+            return;
+        }
         // LCDCの7bit目が0の場合は何もしない
         if self.lcdc & BG_WINDOW_ENABLE == 0 {
             return;
@@ -169,20 +278,67 @@ impl Ppu {
             );
 
             let pixel = self.get_pixel_from_tile(tile_idx, y & 7, x & 7);
-            
+            let shade = (self.bgp >> (pixel << 1)) & 0b11;  // パレットから色を取得
+
+            self.shades[LCD_WIDTH * self.ly as usize + i] = shade;
             self.buffer[LCD_WIDTH * self.ly as usize + i] =
-                match (self.bgp >> (pixel << 1)) & 0b11 {   // パレットから色を取得
+                match shade {
                     0b00 => 0xFF,   // 白
                     0b01 => 0xAA,   // ライトグレー
                     0b10 => 0x55,   // ダークグレー
                     _    => 0x00,   // 黒
                 };
+            // DMG互換モードは色番号をパレット0の色にする
+            if self.model.is_cgb() {
+                self.colors[LCD_WIDTH * self.ly as usize + i] = Self::palette_color(&self.bg_palettes, 0, shade);
+            }
+        }
+    }
+
+    // CGBモードのbgのレンダリング
+    // タイルマップのバンク1にある属性でパレット（bit0-2）・タイルのバンク（bit3）・反転（bit5,6）を選ぶ
+    fn render_bg_cgb(&mut self) {
+        let y = self.ly.wrapping_add(self.scy);
+        for i in 0..LCD_WIDTH {
+            let x = (i as u8).wrapping_add(self.scx);
+            let map_addr = Self::tile_map_addr(self.lcdc & BG_TILE_MAP > 0, y >> 3, x >> 3);
+            let attr = self.vram[0x2000 | map_addr];
+            let tile_idx = self.get_tile_idx_from_tile_map(self.lcdc & BG_TILE_MAP > 0, y >> 3, x >> 3)
+                + if attr & 0x08 > 0 { 0x200 } else { 0 };
+            let row = if attr & 0x40 > 0 { 7 - (y & 7) } else { y & 7 };
+            let col = if attr & 0x20 > 0 { 7 - (x & 7) } else { x & 7 };
+
+            let pixel = self.get_pixel_from_tile(tile_idx, row, col);
+            let color = Self::palette_color(&self.bg_palettes, (attr & 0x07) as usize, pixel);
+            let [r, g, b] = rgb888(color);
+
+            self.shades[LCD_WIDTH * self.ly as usize + i] = pixel;
+            self.colors[LCD_WIDTH * self.ly as usize + i] = color;
+            self.buffer[LCD_WIDTH * self.ly as usize + i] = luma(r, g, b);
         }
     }
 
     // エミュレータサイクル
+    // 153ライン目は1Mサイクル後からLYが0になる
+    fn current_ly(&self) -> u8 {
+        if self.mode == Mode::VBlank && self.ly == 153 && self.cycles < 114 {
+            0
+        } else {
+            self.ly
+        }
+    }
+
+    // ブートROMが終わった時の状態、最後のVBlankでLYが0になった後
+    // DMG0は早く終わるためLYが0に変わる前
+    pub fn skip_boot(&mut self) {
+        self.mode = Mode::VBlank;
+        self.ly = 153;
+        self.cycles = if self.model == Model::Dmg0 { 114 } else { 113 };
+        self.check_lyc_eq_ly();
+    }
+
     fn check_lyc_eq_ly(&mut self){
-        if self.ly == self.lyc {
+        if self.current_ly() == self.lyc {
             self.stat |= LYC_EQ_LY;
         } else {
             self.stat &= !LYC_EQ_LY;
@@ -198,6 +354,9 @@ impl Ppu {
 
         self.cycles -= 1;
         if self.cycles > 0 {
+            if self.mode == Mode::VBlank && self.ly == 153 && self.cycles == 113 {
+                self.check_lyc_eq_ly();
+            }
             return  false;
         }
 
@@ -242,3 +401,128 @@ impl Ppu {
 
 }
 
+impl Snapshot for Ppu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.mode as u8);
+        for val in [self.lcdc, self.stat, self.ly, self.lyc, self.scx, self.scy, self.bgp, self.obp0, self.obp1, self.wy, self.wx, self.cycles,
+                    self.vram_bank, self.bcps, self.ocps] {
+            w.u8(val);
+        }
+        w.bytes(&self.bg_palettes);
+        w.bytes(&self.obj_palettes);
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        w.bytes(&self.buffer);
+        w.bytes(&self.shades);
+        for &c in self.colors.iter() {
+            w.u16(c);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.mode = match r.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid ppu mode")),
+        };
+        for reg in [&mut self.lcdc, &mut self.stat, &mut self.ly, &mut self.lyc, &mut self.scx, &mut self.scy, &mut self.bgp, &mut self.obp0, &mut self.obp1, &mut self.wy, &mut self.wx, &mut self.cycles,
+                    &mut self.vram_bank, &mut self.bcps, &mut self.ocps] {
+            *reg = r.u8()?;
+        }
+        r.bytes_into(&mut self.bg_palettes)?;
+        r.bytes_into(&mut self.obj_palettes)?;
+        r.bytes_into(&mut self.vram)?;
+        r.bytes_into(&mut self.oam)?;
+        r.bytes_into(&mut self.buffer)?;
+        r.bytes_into(&mut self.shades)?;
+        for c in self.colors.iter_mut() {
+            *c = r.u16()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oam_scan(model: Model, row: u8) -> Ppu {
+        let mut ppu = Ppu::new(model, false);
+        ppu.lcdc = PPU_ENABLE;
+        ppu.mode = Mode::OamScan;
+        ppu.cycles = 20 - row;
+        for (i, v) in ppu.oam.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+        ppu
+    }
+
+    #[test]
+    fn oam_bug() {
+        let mut ppu = oam_scan(Model::Dmg, 2);
+        ppu.oam_bug(0xFE40);
+        // 先頭のワードは ((a ^ c) & (b ^ c)) ^ c、残りは直前の行のコピー
+        let (a, b, c) = (0x1110u16, 0x0908u16, 0x0D0Cu16);
+        assert_eq!(ppu.oam[16..18], (((a ^ c) & (b ^ c)) ^ c).to_le_bytes());
+        assert_eq!(ppu.oam[18..24], [10, 11, 12, 13, 14, 15]);
+        assert_eq!(ppu.oam[8..16], [8, 9, 10, 11, 12, 13, 14, 15]);
+
+        // CGB、OAM以外のアドレス、先頭の行では起きない
+        for (model, row, addr) in [(Model::Cgb, 2, 0xFE40), (Model::Dmg, 2, 0xC000), (Model::Dmg, 0, 0xFE00)] {
+            let mut ppu = oam_scan(model, row);
+            let before = ppu.oam.clone();
+            ppu.oam_bug(addr);
+            assert_eq!(ppu.oam, before);
+        }
+    }
+
+    #[test]
+    fn cgb_palettes() {
+        let mut ppu = Ppu::new(Model::Cgb, true);
+        ppu.write(0xFF68, 0x80 | 0x3E);
+        ppu.write(0xFF69, 0x12);
+        ppu.write(0xFF69, 0x34);    // 0x3F の次は0に戻る
+        assert_eq!(ppu.read(0xFF68), 0xC0);     // 自動インクリメントのbit7と未使用のbit6
+        assert_eq!(ppu.bg_palettes[0x3E..], [0x12, 0x34]);
+        assert_eq!(ppu.bg_palettes[0], 0x00);
+
+        ppu.write(0xFF4F, 0x01);
+        ppu.write(0x8000, 0x55);
+        assert_eq!(ppu.read(0xFF4F), 0xFF);
+        assert_eq!(ppu.vram[0x2000], 0x55);
+        assert_eq!(ppu.vram[0x0000], 0x00);
+
+        // DMG互換モードではCGBのレジスタは使えない
+        let mut ppu = Ppu::new(Model::Cgb, false);
+        ppu.write(0xFF4F, 0x01);
+        assert_eq!(ppu.read(0xFF4F), 0xFF);
+        assert_eq!(ppu.read(0xFF69), 0xFF);
+    }
+
+    #[test]
+    fn cgb_render() {
+        // DMG互換モードはBGPの色番号を互換パレットで色にする
+        let mut ppu = Ppu::new(Model::Cgb, false);
+        ppu.lcdc = PPU_ENABLE | TILE_DATA_ADDRESSING_MODE | BG_WINDOW_ENABLE;
+        ppu.bgp = 0xE4;
+        ppu.vram[0] = 0xF0;
+        ppu.render_bg();
+        assert_eq!(ppu.colors[0], COMPAT_PALETTE[1]);
+        assert_eq!(ppu.colors[4], COMPAT_PALETTE[0]);
+        assert!(ppu.rgb().is_some());
+        assert!(Ppu::new(Model::Dmg, false).rgb().is_none());
+
+        // CGBモードは属性のパレット・バンク・反転を使う
+        let mut ppu = Ppu::new(Model::Cgb, true);
+        ppu.lcdc = PPU_ENABLE | TILE_DATA_ADDRESSING_MODE;
+        ppu.vram[0x2000 | 0x1800] = 0x08 | 0x20 | 0x03;    // バンク1、左右反転、パレット3
+        ppu.vram[0x2000] = 0x01;                            // バンク1のタイル0、右端の1ドット
+        ppu.bg_palettes[3 * 8 + 2..3 * 8 + 4].copy_from_slice(&0x001Fu16.to_le_bytes());
+        ppu.render_bg();
+        assert_eq!(ppu.colors[0], 0x001F);
+        assert_eq!(ppu.shades[0], 1);
+        assert_eq!(ppu.colors[7], 0x0000);
+    }
+}
@@ -1,6 +1,10 @@
 // CPUレジスタ
 #![allow(dead_code)]
 
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
 
 #[derive(Default, Clone)]
 pub struct Registers {
@@ -114,4 +118,23 @@ impl Registers {
     }
     
 
-}
\ No newline at end of file
+}
+
+impl Snapshot for Registers {
+    fn save_state(&self, w: &mut StateWriter) {
+        for val in [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l] {
+            w.u8(val);
+        }
+        w.u16(self.pc);
+        w.u16(self.sp);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for reg in [&mut self.a, &mut self.b, &mut self.c, &mut self.d, &mut self.e, &mut self.f, &mut self.h, &mut self.l] {
+            *reg = r.u8()?;
+        }
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+// 巻き戻し
+// 数フレーム毎にステートを保存し、1つ新しいステートとのXORをランレングス圧縮して持つ
+// 最新のステートだけは圧縮せずに持ち、古い方へ差分を順にたどる
+// 保存の間のフレームはボタンの状態を記録しておき、巻き戻し時に再生する
+
+use std::collections::VecDeque;
+
+use crate::{console::Console, state};
+
+pub const DEFAULT_INTERVAL: usize = 4;
+pub const DEFAULT_BUDGET: usize = 32 << 20;      // 32MB
+
+// ステート1つ分の差分と、そのステートから次のステートまでの入力
+struct Segment {
+    delta: Vec<u8>,
+    inputs: Vec<u8>,
+}
+
+pub struct Rewind {
+    interval: usize,            // 何フレーム毎に保存するか
+    budget: usize,              // 使用するメモリの上限[byte]
+    latest: Option<Vec<u8>>,    // 最新のステート
+    inputs: Vec<u8>,            // 最新のステート以降のフレーム毎の入力
+    segments: VecDeque<Segment>,
+    size: usize,                // segments の合計サイズ
+}
+
+impl Rewind {
+    pub fn new(interval: usize, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            latest: None,
+            inputs: Vec::new(),
+            segments: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    // フレームの終わりに呼ぶ
+    pub fn end_frame(&mut self, console: &Console) {
+        let Some(ref latest) = self.latest else {
+            self.latest = Some(state::save(console));
+            return;
+        };
+        self.inputs.push(console.buttons());
+        if self.inputs.len() < self.interval {
+            return;
+        }
+
+        let current = state::save(console);
+        let delta = compress(&diff(&current, latest));
+        self.size += delta.len() + self.inputs.len();
+        self.segments.push_back(Segment { delta, inputs: std::mem::take(&mut self.inputs) });
+        self.latest = Some(current);
+
+        // 上限を超えたら古いものから捨てる
+        let latest_len = self.latest.as_ref().map_or(0, Vec::len);
+        while self.size + latest_len > self.budget {
+            let Some(segment) = self.segments.pop_front() else {
+                break;
+            };
+            self.size -= segment.delta.len() + segment.inputs.len();
+        }
+    }
+
+    // 1フレーム戻す、戻せなければ false
+    // 保存したステートを読み込み、戻したいフレームまでの入力を再生する
+    pub fn step_back(&mut self, console: &mut Console) -> bool {
+        if self.inputs.is_empty() {
+            let Some(segment) = self.segments.pop_back() else {
+                return false;
+            };
+            self.size -= segment.delta.len() + segment.inputs.len();
+            let Some(ref mut latest) = self.latest else {
+                return false;
+            };
+            *latest = undiff(latest, &decompress(&segment.delta));
+            self.inputs = segment.inputs;
+        }
+        self.inputs.pop();
+
+        let Some(ref latest) = self.latest else {
+            return false;
+        };
+        if let Err(e) = state::load(console, latest) {
+            eprintln!("Cannot rewind: {}", e);
+            return false;
+        }
+        for &buttons in self.inputs.iter() {
+            console.set_buttons(buttons);
+            console.run_frame();
+        }
+        true
+    }
+
+    // 保存しているフレーム数
+    pub fn frames(&self) -> usize {
+        self.segments.iter().map(|segment| segment.inputs.len()).sum::<usize>() + self.inputs.len()
+    }
+}
+
+// 新しいステートから古いステートへの差分
+// 古い方の長さ（4byte）と、長い方に合わせて0で埋めたXOR
+fn diff(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let len = newer.len().max(older.len());
+    let mut ret = Vec::with_capacity(len + 4);
+    ret.extend((older.len() as u32).to_le_bytes());
+    ret.extend((0..len).map(|i| newer.get(i).unwrap_or(&0) ^ older.get(i).unwrap_or(&0)));
+    ret
+}
+
+// 差分を適用して古いステートを復元する
+fn undiff(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let Some((len, delta)) = delta.split_first_chunk::<4>() else {
+        return Vec::new();
+    };
+    let len = u32::from_le_bytes(*len) as usize;
+    (0..len).map(|i| newer.get(i).unwrap_or(&0) ^ delta.get(i).unwrap_or(&0)).collect()
+}
+
+// ランレングス圧縮（0の連続のみ）
+// 0の個数、続く非0のbyte数、非0のbyte列、の繰り返し（個数はLEB128）
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+        let literal = data[i..].iter().take_while(|&&b| b != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal);
+        out.extend_from_slice(&data[i..i + literal]);
+        i += literal;
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = read_varint(data, &mut i);
+        out.resize(out.len() + zeros, 0);
+        let literal = read_varint(data, &mut i);
+        let end = (i + literal).min(data.len());
+        out.extend_from_slice(&data[i..end]);
+        i = end;
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    while let Some(&b) = data.get(*i) {
+        *i += 1;
+        val |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    val
+}
@@ -0,0 +1,55 @@
+// バッテリーバックアップされたSRAMの保存
+// ROMと同じ場所の .sav にSRAMをそのまま書き込む（他のエミュレータやフラッシュカートと互換）
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::cartridge::Cartridge;
+
+pub struct SaveFile {
+    path: PathBuf,
+    last: Vec<u8>,      // 最後に保存したSRAM
+}
+
+impl SaveFile {
+    pub fn new(rom_path: &Path) -> Self {
+        Self {
+            path: rom_path.with_extension("sav"),
+            last: vec![],
+        }
+    }
+
+    // .savがあれば読み込む
+    pub fn load(&mut self, cartridge: &mut Cartridge) -> io::Result<bool> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        cartridge.load_save_data(&data);
+        self.last = cartridge.sram.clone();
+        Ok(true)
+    }
+
+    // SRAMが変化していれば保存する
+    pub fn flush(&mut self, cartridge: &Cartridge) -> io::Result<()> {
+        if cartridge.sram == self.last {
+            return Ok(());
+        }
+        self.save(cartridge)
+    }
+
+    // 保存する、途中で終了しても壊れないよう一時ファイルに書いてから置き換える
+    pub fn save(&mut self, cartridge: &Cartridge) -> io::Result<()> {
+        let tmp = self.path.with_extension("sav.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&cartridge.save_data())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.last = cartridge.sram.clone();
+        Ok(())
+    }
+}
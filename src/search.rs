@@ -0,0 +1,244 @@
+// RAM検索（チート探し・ボット用）
+// WRAM・HRAM・カートリッジのSRAMのスナップショットを取り、条件で候補を絞り込む
+// 読み込みは Peripherals::read を使う（&self なので副作用なし）
+
+use crate::{cpu::interrupts::Interrupts, peripherals::Peripherals};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Sram,   // 0xA000～0xBFFF
+    Wram,   // 0xC000～0xDFFF
+    Hram,   // 0xFF80～0xFFFE
+}
+
+impl Region {
+    pub const ALL: [Region; 3] = [Region::Sram, Region::Wram, Region::Hram];
+
+    pub fn range(self) -> std::ops::RangeInclusive<u16> {
+        match self {
+            Self::Sram => 0xA000..=0xBFFF,
+            Self::Wram => 0xC000..=0xDFFF,
+            Self::Hram => 0xFF80..=0xFFFE,
+        }
+    }
+}
+
+// 値の解釈
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    U8,
+    U16Le,
+    U16Be,
+    Bcd8,
+    Bcd16Le,
+    Bcd16Be,
+}
+
+impl ValueType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "u8"      => Some(Self::U8),
+            "u16le"   => Some(Self::U16Le),
+            "u16be"   => Some(Self::U16Be),
+            "bcd8"    => Some(Self::Bcd8),
+            "bcd16le" => Some(Self::Bcd16Le),
+            "bcd16be" => Some(Self::Bcd16Be),
+            _         => None,
+        }
+    }
+
+    // 値のバイト数
+    pub fn size(self) -> u16 {
+        match self {
+            Self::U8 | Self::Bcd8 => 1,
+            _                     => 2,
+        }
+    }
+
+    // バイト列から値を取り出す、BCDとして不正な場合は None
+    fn decode(self, lo: u8, hi: u8) -> Option<u32> {
+        let bcd = |b: u8| ((b >> 4) < 10 && (b & 0xF) < 10).then(|| ((b >> 4) * 10 + (b & 0xF)) as u32);
+        match self {
+            Self::U8      => Some(lo as u32),
+            Self::U16Le   => Some(u16::from_le_bytes([lo, hi]) as u32),
+            Self::U16Be   => Some(u16::from_be_bytes([lo, hi]) as u32),
+            Self::Bcd8    => bcd(lo),
+            Self::Bcd16Le => Some(bcd(hi)? * 100 + bcd(lo)?),
+            Self::Bcd16Be => Some(bcd(lo)? * 100 + bcd(hi)?),
+        }
+    }
+}
+
+// 絞り込み条件（比較対象は前回のスナップショット）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Equal,          // 変化なし
+    Changed,        // 変化あり
+    Increased,
+    Decreased,
+    Value(u32),     // 指定の値
+}
+
+// 候補のアドレスと前回の値
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u16,
+    pub value: u32,
+}
+
+pub struct RamSearch {
+    value_type: ValueType,
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    // 指定領域の全アドレスを候補にして検索を開始する
+    pub fn new(bus: &Peripherals, interrupts: &Interrupts, regions: &[Region], value_type: ValueType) -> Self {
+        let mut search = Self {
+            value_type,
+            candidates: regions.iter()
+                .flat_map(|region| {
+                    let range = region.range();
+                    // 16bitの場合は領域をまたがない
+                    (*range.start()..=*range.end() + 1 - value_type.size())
+                        .map(|addr| Candidate { addr, value: 0 })
+                })
+                .collect(),
+        };
+        search.snapshot(bus, interrupts);
+        search
+    }
+
+    fn read(&self, bus: &Peripherals, interrupts: &Interrupts, addr: u16) -> Option<u32> {
+        let lo = bus.read(interrupts, addr);
+        let hi = if self.value_type.size() == 2 { bus.read(interrupts, addr.wrapping_add(1)) } else { 0 };
+        self.value_type.decode(lo, hi)
+    }
+
+    // 現在の値を記録する、BCDとして読めなくなった候補は外す
+    pub fn snapshot(&mut self, bus: &Peripherals, interrupts: &Interrupts) {
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain_mut(|candidate| match self.read(bus, interrupts, candidate.addr) {
+            Some(value) => {
+                candidate.value = value;
+                true
+            },
+            None => false,
+        });
+        self.candidates = candidates;
+    }
+
+    // 前回のスナップショットと比べて候補を絞り込み、現在の値を記録する
+    pub fn filter(&mut self, bus: &Peripherals, interrupts: &Interrupts, filter: Filter) -> usize {
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain_mut(|candidate| {
+            let Some(value) = self.read(bus, interrupts, candidate.addr) else {
+                return false;
+            };
+            let keep = match filter {
+                Filter::Equal     => value == candidate.value,
+                Filter::Changed   => value != candidate.value,
+                Filter::Increased => value > candidate.value,
+                Filter::Decreased => value < candidate.value,
+                Filter::Value(v)  => value == v,
+            };
+            candidate.value = value;
+            keep
+        });
+        self.candidates = candidates;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bootrom::Bootrom, cartridge::{Cartridge, NINTENDO_LOGO}, model::Model};
+
+    fn peripherals() -> (Peripherals, Interrupts) {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        let cartridge = Cartridge::from_bytes(rom, false).unwrap();
+        (Peripherals::new(Bootrom::new(), cartridge, Model::Dmg), Interrupts::default())
+    }
+
+    fn addrs(search: &RamSearch) -> Vec<u16> {
+        search.candidates().iter().map(|c| c.addr).collect()
+    }
+
+    #[test]
+    fn filters() {
+        let (mut bus, mut int) = peripherals();
+        let mut search = RamSearch::new(&bus, &int, &[Region::Wram], ValueType::U8);
+        assert_eq!(search.len(), 0x2000);
+
+        bus.write(&mut int, 0xC000, 0x10);
+        bus.write(&mut int, 0xC001, 0x20);
+        assert_eq!(search.filter(&bus, &int, Filter::Changed), 2);
+        assert_eq!(addrs(&search), [0xC000, 0xC001]);
+
+        bus.write(&mut int, 0xC000, 0x11);
+        bus.write(&mut int, 0xC001, 0x1F);
+        search.snapshot(&bus, &int);
+        bus.write(&mut int, 0xC000, 0x12);
+        bus.write(&mut int, 0xC001, 0x1E);
+        let mut decreased = RamSearch { value_type: ValueType::U8, candidates: search.candidates().to_vec() };
+        assert_eq!(search.filter(&bus, &int, Filter::Increased), 1);
+        assert_eq!(addrs(&search), [0xC000]);
+        assert_eq!(decreased.filter(&bus, &int, Filter::Decreased), 1);
+        assert_eq!(addrs(&decreased), [0xC001]);
+
+        assert_eq!(search.filter(&bus, &int, Filter::Equal), 1);
+        bus.write(&mut int, 0xC000, 0x13);
+        assert_eq!(search.filter(&bus, &int, Filter::Equal), 0);
+
+        let mut search = RamSearch::new(&bus, &int, &[Region::Wram, Region::Hram], ValueType::U8);
+        bus.write(&mut int, 0xFF90, 0x13);
+        assert_eq!(search.filter(&bus, &int, Filter::Value(0x13)), 2);
+        assert_eq!(addrs(&search), [0xC000, 0xFF90]);
+        assert_eq!(search.candidates()[1].value, 0x13);
+    }
+
+    #[test]
+    fn bcd() {
+        assert_eq!(ValueType::Bcd8.decode(0x42, 0x00), Some(42));
+        assert_eq!(ValueType::Bcd16Le.decode(0x34, 0x12), Some(1234));
+        assert_eq!(ValueType::Bcd16Be.decode(0x12, 0x34), Some(1234));
+        assert_eq!(ValueType::Bcd8.decode(0x1A, 0x00), None);
+        assert_eq!(ValueType::Bcd16Le.decode(0x34, 0xA2), None);
+        assert_eq!(ValueType::Bcd16Be.decode(0x12, 0x3F), None);
+
+        // BCDとして読めなくなった候補は外れる
+        let (mut bus, mut int) = peripherals();
+        let mut search = RamSearch::new(&bus, &int, &[Region::Hram], ValueType::Bcd8);
+        assert_eq!(search.len(), 0x7F);
+        bus.write(&mut int, 0xFF80, 0x1A);
+        bus.write(&mut int, 0xFF81, 0x19);
+        assert_eq!(search.filter(&bus, &int, Filter::Changed), 1);
+        assert_eq!(search.candidates(), [Candidate { addr: 0xFF81, value: 19 }]);
+    }
+
+    // 16bitの候補は領域の最後のアドレス（0xDFFF・0xFFFE）を越えて読まない
+    #[test]
+    fn range_end() {
+        let (bus, int) = peripherals();
+        let search = RamSearch::new(&bus, &int, &[Region::Wram, Region::Hram], ValueType::U16Le);
+        assert_eq!(search.len(), 0x1FFF + 0x7E);
+        let addrs = addrs(&search);
+        assert!(addrs.contains(&0xDFFE) && !addrs.contains(&0xDFFF));
+        assert_eq!(addrs.last(), Some(&0xFFFD));
+        assert_eq!(ValueType::from_name("BCD16LE"), Some(ValueType::Bcd16Le));
+    }
+}
@@ -0,0 +1,162 @@
+// シリアル通信（SB・SC）
+// 内部クロックは8192Hz（CGBの高速モードは262144Hz）で1bitずつ送受信し、8bitで割り込み
+// 外部クロックの場合は相手がクロックを供給するまで待つ
+
+use std::io;
+
+use crate::{
+    cpu::interrupts::{Interrupts, SERIAL},
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+pub mod cable;
+pub mod capture;
+pub mod link;
+pub mod printer;
+
+const TRANSFER_START: u8 = 1 << 7;
+const CLOCK_SPEED: u8 = 1 << 1;         // CGBのみ
+const INTERNAL_CLOCK: u8 = 1 << 0;
+
+const BIT_CYCLES: u16 = 128;            // 8192Hz、Mサイクル
+const FAST_BIT_CYCLES: u16 = 4;         // 262144Hz
+
+// ケーブルの先につながる機器
+pub trait SerialDevice {
+    // 内部クロックで転送を始めた時、送信した1byteに対する相手の1byte
+    fn transfer(&mut self, val: u8) -> u8;
+
+    // 外部クロックで待っている時、相手がクロックを供給すれば相手の1byte
+    fn external_clock(&mut self, _val: u8) -> Option<u8> {
+        None
+    }
+
+    // Mサイクル毎の処理（相手との同期用）
+    fn emulate_cycle(&mut self) {}
+}
+
+// 何もつながっていない（受信は常に0xFF）
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _: u8) -> u8 {
+        0xFF
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb_mode: bool,
+    bits: u8,           // 残りbit数
+    cycles: u16,
+    incoming: u8,       // 相手から受信する1byte
+    device: Box<dyn SerialDevice>,
+}
+
+impl Serial {
+    pub fn new(cgb_mode: bool) -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            cgb_mode,
+            bits: 0,
+            cycles: 0,
+            incoming: 0xFF,
+            device: Box::new(Disconnected),
+        }
+    }
+
+    // 機器の接続
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => if self.cgb_mode { 0x7C | self.sc } else { 0x7E | self.sc },
+            _      => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val & (TRANSFER_START | INTERNAL_CLOCK | if self.cgb_mode { CLOCK_SPEED } else { 0 });
+                if self.sc & TRANSFER_START != 0 && self.sc & INTERNAL_CLOCK != 0 {
+                    // 内部クロック、相手の値は最初に受け取り1bitずつ取り込む
+                    self.incoming = self.device.transfer(self.sb);
+                    self.bits = 8;
+                    self.cycles = self.bit_cycles();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn bit_cycles(&self) -> u16 {
+        if self.sc & CLOCK_SPEED != 0 { FAST_BIT_CYCLES } else { BIT_CYCLES }
+    }
+
+    // 転送完了
+    fn complete(&mut self, interrupts: &mut Interrupts) {
+        self.sc &= !TRANSFER_START;
+        interrupts.irq(SERIAL);
+    }
+
+    // Mサイクル毎の処理
+    pub fn emulate_cycle(&mut self, interrupts: &mut Interrupts) {
+        self.transfer_cycle(interrupts);
+        self.device.emulate_cycle();
+    }
+
+    fn transfer_cycle(&mut self, interrupts: &mut Interrupts) {
+        if self.sc & TRANSFER_START == 0 {
+            return;
+        }
+
+        // 外部クロック
+        if self.sc & INTERNAL_CLOCK == 0 {
+            if let Some(val) = self.device.external_clock(self.sb) {
+                self.sb = val;
+                self.complete(interrupts);
+            }
+            return;
+        }
+
+        // 内部クロック
+        self.cycles -= 1;
+        if self.cycles > 0 {
+            return;
+        }
+        self.bits -= 1;
+        self.sb = (self.sb << 1) | ((self.incoming >> self.bits) & 1);
+        if self.bits == 0 {
+            self.complete(interrupts);
+        } else {
+            self.cycles = self.bit_cycles();
+        }
+    }
+}
+
+// 接続先の機器は保存しない
+impl Snapshot for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u8(self.bits);
+        w.u16(self.cycles);
+        w.u8(self.incoming);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.bits = r.u8()?;
+        self.cycles = r.u16()?;
+        self.incoming = r.u8()?;
+        Ok(())
+    }
+}
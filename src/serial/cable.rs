@@ -0,0 +1,53 @@
+// 同じプロセス内の2台をつなぐ仮想の通信ケーブル
+// 2台は1Mサイクルずつ交互に進めるため、相手の状態はずれても1Mサイクル分
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::serial::SerialDevice;
+
+#[derive(Default)]
+struct End {
+    cycles: u64,
+    waiting: Option<(u8, u64)>,     // 外部クロックで待っているSBとそのサイクル
+    received: Option<u8>,           // 相手のクロックで受け取った値
+}
+
+pub struct CableEnd {
+    ends: Rc<RefCell<[End; 2]>>,
+    side: usize,
+}
+
+// ケーブルの両端を作成する
+pub fn pair() -> (CableEnd, CableEnd) {
+    let ends = Rc::new(RefCell::new([End::default(), End::default()]));
+    (CableEnd { ends: ends.clone(), side: 0 }, CableEnd { ends, side: 1 })
+}
+
+impl SerialDevice for CableEnd {
+    // 相手が直前のサイクルで外部クロックを待っていれば交換する
+    fn transfer(&mut self, val: u8) -> u8 {
+        let mut ends = self.ends.borrow_mut();
+        let peer = &mut ends[1 - self.side];
+        match peer.waiting.take() {
+            Some((sb, cycle)) if cycle + 1 == peer.cycles => {
+                peer.received = Some(val);
+                sb
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn external_clock(&mut self, val: u8) -> Option<u8> {
+        let mut ends = self.ends.borrow_mut();
+        let end = &mut ends[self.side];
+        let received = end.received.take();
+        if received.is_none() {
+            end.waiting = Some((val, end.cycles));
+        }
+        received
+    }
+
+    fn emulate_cycle(&mut self) {
+        self.ends.borrow_mut()[self.side].cycles += 1;
+    }
+}
@@ -0,0 +1,58 @@
+// シリアル出力の記録（BlarggのテストROMなど）
+// テストROMは結果の文字をSBに書き込み、SCで転送を始める
+
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
+use crate::serial::SerialDevice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    Failed,
+}
+
+pub struct Capture {
+    text: Rc<RefCell<String>>,
+    echo: bool,             // 受け取った文字を標準出力に表示する
+}
+
+// 記録した文字列の参照（シリアルにつないだ後も読める）
+#[derive(Clone)]
+pub struct CaptureOutput {
+    text: Rc<RefCell<String>>,
+    checked: Cell<usize>,   // 前回判定した時の長さ
+}
+
+impl Capture {
+    pub fn new(echo: bool) -> (Self, CaptureOutput) {
+        let text = Rc::new(RefCell::new(String::new()));
+        (Self { text: text.clone(), echo }, CaptureOutput { text, checked: Cell::new(0) })
+    }
+}
+
+impl SerialDevice for Capture {
+    fn transfer(&mut self, val: u8) -> u8 {
+        let c = val as char;
+        self.text.borrow_mut().push(c);
+        if self.echo {
+            print!("{}", c);
+        }
+        0xFF
+    }
+}
+
+impl CaptureOutput {
+    // "Passed" か "Failed" が出力されたか、毎サイクル呼べるよう増えた時だけ調べる
+    pub fn result(&self) -> Option<TestResult> {
+        let text = self.text.borrow();
+        if text.len() == self.checked.replace(text.len()) {
+            None
+        } else if text.contains("Passed") {
+            Some(TestResult::Passed)
+        } else if text.contains("Failed") {
+            Some(TestResult::Failed)
+        } else {
+            None
+        }
+    }
+}
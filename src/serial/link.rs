@@ -0,0 +1,245 @@
+// 通信ケーブル（TCP・Unixドメインソケットで2つのエミュレータをつなぐ）
+// 同期方法：
+//   お互いに QUANTUM_CYCLES 毎に SYNC を送り、相手の SYNC を受け取るまで待つ
+//   内部クロック側は TRANSFER を送り、REPLY を受け取るまで止まる
+//   相手の TRANSFER は区切りで処理し、その時に外部クロックで待っていればSBを返す
+// どちらの処理も区切りの位置で決まるため、通信の速さによらず結果は同じになる
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::serial::SerialDevice;
+
+const QUANTUM_CYCLES: u32 = 512;    // 同期の間隔（Mサイクル）
+
+// メッセージの種類、2byte目は値
+const SYNC: u8 = 0x00;
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+pub struct Link {
+    stream: Option<Box<dyn Stream>>,    // 切断されたら None
+    cycles: u32,
+    quantum: u64,
+    peer_syncs: u64,        // 相手から受け取った SYNC の数
+    waiting: Option<u8>,    // このサイクルで外部クロックを待っているSB
+    received: Option<u8>,   // 外部クロックで受け取った値
+}
+
+impl Link {
+    // 接続を待つ、"unix:パス" はUnixドメインソケット
+    pub fn listen(addr: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(path) = addr.strip_prefix("unix:") {
+            use std::os::unix::fs::FileTypeExt;
+            // 前回のソケットが残っていれば消す、ソケット以外のファイルは消さない
+            if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let (stream, _) = std::os::unix::net::UnixListener::bind(path)?.accept()?;
+            return Ok(Self::new(Box::new(stream)));
+        }
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(Box::new(stream)))
+    }
+
+    // 接続する
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(path) = addr.strip_prefix("unix:") {
+            let stream = std::os::unix::net::UnixStream::connect(path)?;
+            return Ok(Self::new(Box::new(stream)));
+        }
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(Box::new(stream)))
+    }
+
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            stream: Some(stream),
+            cycles: 0,
+            quantum: 0,
+            peer_syncs: 0,
+            waiting: None,
+            received: None,
+        }
+    }
+
+    fn send(&mut self, kind: u8, val: u8) {
+        if let Some(ref mut stream) = self.stream {
+            if let Err(e) = stream.write_all(&[kind, val]) {
+                self.disconnect(e);
+            }
+        }
+    }
+
+    fn recv(&mut self) -> Option<(u8, u8)> {
+        let stream = self.stream.as_mut()?;
+        let mut buf = [0; 2];
+        match stream.read_exact(&mut buf) {
+            Ok(())  => Some((buf[0], buf[1])),
+            Err(e)  => {
+                self.disconnect(e);
+                None
+            },
+        }
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        eprintln!("Link cable disconnected: {}", e);
+        self.stream = None;
+    }
+}
+
+impl SerialDevice for Link {
+    // 内部クロック：相手の応答まで待つ
+    fn transfer(&mut self, val: u8) -> u8 {
+        self.send(TRANSFER, val);
+        loop {
+            match self.recv() {
+                Some((REPLY, reply)) => return reply,
+                Some((SYNC, _))      => self.peer_syncs += 1,
+                // 相手も内部クロックで送っている場合、どちらもクロックを受けない
+                Some((TRANSFER, _))  => self.send(REPLY, 0xFF),
+                Some(_)              => (),
+                None                 => return 0xFF,
+            }
+        }
+    }
+
+    fn external_clock(&mut self, val: u8) -> Option<u8> {
+        let received = self.received.take();
+        if received.is_none() {
+            self.waiting = Some(val);
+        }
+        received
+    }
+
+    fn emulate_cycle(&mut self) {
+        self.cycles += 1;
+        if self.cycles >= QUANTUM_CYCLES {
+            self.cycles = 0;
+            self.quantum += 1;
+            self.send(SYNC, 0);
+            // 相手がこの区切りに来るまでのメッセージを処理する
+            while self.stream.is_some() && self.peer_syncs < self.quantum {
+                match self.recv() {
+                    Some((SYNC, _))       => self.peer_syncs += 1,
+                    Some((TRANSFER, val)) => match self.waiting {
+                        Some(sb) => {
+                            self.send(REPLY, sb);
+                            self.received = Some(val);
+                        },
+                        None => self.send(REPLY, 0xFF),
+                    },
+                    _ => (),
+                }
+            }
+        }
+        self.waiting = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        cpu::interrupts::{Interrupts, SERIAL},
+        serial::Serial,
+    };
+
+    const TEST_CYCLES: u32 = QUANTUM_CYCLES * 8;
+
+    // start サイクル目にSBを設定して転送を始め、TEST_CYCLES まで進める
+    // 戻り値は転送後のSBと、転送が完了して割り込みが要求されたか
+    fn exchange(link: Link, internal: bool, sb: u8, start: u32) -> (u8, bool) {
+        let mut serial = Serial::new(false);
+        let mut interrupts = Interrupts::default();
+        serial.connect(Box::new(link));
+        for cycle in 0..TEST_CYCLES {
+            if cycle == start {
+                serial.write(0xFF01, sb);
+                serial.write(0xFF02, if internal { 0x81 } else { 0x80 });
+            }
+            serial.emulate_cycle(&mut interrupts);
+        }
+        let done = serial.read(0xFF02) & 0x80 == 0 && interrupts.int_flags & SERIAL != 0;
+        (serial.read(0xFF01), done)
+    }
+
+    // 2つのスレッドで接続し、外部クロック側が待っている所へ内部クロック側が送る
+    fn run_pair(addr: &str, listener_internal: bool) {
+        let (tx, rx) = mpsc::channel();
+        let listen_addr = addr.to_string();
+        let listen_tx = tx.clone();
+        thread::spawn(move || {
+            let link = Link::listen(&listen_addr).unwrap();
+            let start = if listener_internal { 100 } else { 0 };
+            listen_tx.send((0, exchange(link, listener_internal, 0x42, start))).unwrap();
+        });
+        let connect_addr = addr.to_string();
+        thread::spawn(move || {
+            let link = loop {
+                match Link::connect(&connect_addr) {
+                    Ok(link) => break link,
+                    Err(_)   => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            let start = if listener_internal { 0 } else { 100 };
+            tx.send((1, exchange(link, !listener_internal, 0x24, start))).unwrap();
+        });
+
+        let mut results = [(0, false); 2];
+        for _ in 0..2 {
+            let (side, result) = rx.recv_timeout(Duration::from_secs(10)).expect("link cable deadlocked");
+            results[side] = result;
+        }
+        assert_eq!(results, [(0x24, true), (0x42, true)]);
+    }
+
+    fn tcp_addr() -> String {
+        // 空いているポートを探す
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        format!("127.0.0.1:{}", port)
+    }
+
+    #[test]
+    fn tcp_transfers() {
+        run_pair(&tcp_addr(), false);
+        run_pair(&tcp_addr(), true);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_transfers() {
+        let path = std::env::temp_dir().join(format!("gb-link-test-{}.sock", std::process::id()));
+        let addr = format!("unix:{}", path.display());
+        run_pair(&addr, false);
+        // 前回のソケットが残っていても接続できる
+        run_pair(&addr, true);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_listen_keeps_regular_file() {
+        let path = std::env::temp_dir().join(format!("gb-link-test-{}.txt", std::process::id()));
+        std::fs::write(&path, b"keep").unwrap();
+        assert!(Link::listen(&format!("unix:{}", path.display())).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"keep");
+        let _ = std::fs::remove_file(&path);
+    }
+}
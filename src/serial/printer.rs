@@ -0,0 +1,235 @@
+// ポケットプリンタ
+// パケット：0x88 0x33、コマンド、圧縮フラグ、長さ（2byte）、データ、チェックサム（2byte）、0x00 0x00
+// 最後の2byteに対してプリンタは 0x81 とステータスを返す
+
+use std::path::PathBuf;
+
+use crate::{image, serial::SerialDevice, LCD_WIDTH};
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// ステータス
+const CHECKSUM_ERROR: u8 = 1 << 0;
+const PRINTING: u8 = 1 << 1;
+const IMAGE_FULL: u8 = 1 << 2;
+const UNPROCESSED: u8 = 1 << 3;
+
+const BAND_SIZE: usize = 0x280;         // DATA 1パケット分（20x2タイル）
+const BUFFER_SIZE: usize = BAND_SIZE * 9;
+const PRINT_CYCLES: u32 = 17556 * 30;   // 印刷中の時間（約0.5秒、Mサイクル）
+const MARGIN_LINES: usize = 8;          // 余白1単位のドット数
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    dir: PathBuf,
+    extension: &'static str,    // png か bmp
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    status: u8,
+    buffer: Vec<u8>,            // 受け取った画像データ（2bppタイル）
+    paper: Vec<u8>,             // 余白で区切られるまでつながった印刷結果
+    busy: u32,
+    count: usize,
+}
+
+impl Printer {
+    pub fn new(dir: PathBuf, extension: &'static str) -> Self {
+        Self {
+            dir,
+            extension,
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            status: 0,
+            buffer: Vec::new(),
+            paper: Vec::new(),
+            busy: 0,
+            count: 0,
+        }
+    }
+
+    // 受け取ったパケットの処理
+    fn execute(&mut self, received: u16) {
+        let sum = [self.command, self.compressed as u8, self.length as u8, (self.length >> 8) as u8]
+            .iter()
+            .chain(self.data.iter())
+            .fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        if sum != received {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            },
+            DATA => {
+                let data = if self.compressed { decompress(&self.data) } else { self.data.clone() };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                self.status |= UNPROCESSED;
+                if self.buffer.len() >= BUFFER_SIZE {
+                    self.status |= IMAGE_FULL;
+                }
+            },
+            PRINT if self.data.len() >= 4 => {
+                // データ：枚数、余白（上位4bitが前、下位4bitが後）、パレット、濃度
+                let margin_before = (self.data[1] >> 4) as usize;
+                let margin_after = (self.data[1] & 0x0F) as usize;
+                let palette = if self.data[2] == 0 { 0xE4 } else { self.data[2] };
+                self.print(margin_before, margin_after, palette);
+                self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | PRINTING;
+                self.busy = PRINT_CYCLES;
+            },
+            STATUS => (),   // ステータスを返すだけ
+            _ => (),
+        }
+    }
+
+    // 画像を紙に追加し、後ろの余白があれば1枚として保存する
+    fn print(&mut self, margin_before: usize, margin_after: usize, palette: u8) {
+        let height = self.buffer.len() / BAND_SIZE * 16;
+        self.paper.resize(self.paper.len() + margin_before * MARGIN_LINES * LCD_WIDTH, 0xFF);
+        for y in 0..height {
+            for x in 0..LCD_WIDTH {
+                let offset = ((y >> 3) * (LCD_WIDTH >> 3) + (x >> 3)) * 16 + (y & 7) * 2;
+                let bit = 7 - (x & 7);
+                let color = (((self.buffer[offset + 1] >> bit) & 1) << 1) | ((self.buffer[offset] >> bit) & 1);
+                let shade = (palette >> (color * 2)) & 0x03;
+                self.paper.push([0xFF, 0xAA, 0x55, 0x00][shade as usize]);
+            }
+        }
+        self.buffer.clear();
+        if margin_after > 0 {
+            self.paper.resize(self.paper.len() + margin_after * MARGIN_LINES * LCD_WIDTH, 0xFF);
+            self.save();
+        }
+    }
+
+    // 紙を画像として保存する
+    fn save(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+        self.count += 1;
+        let path = self.dir.join(format!("print_{:04}.{}", self.count, self.extension));
+        match image::save_gray(&path, LCD_WIDTH, self.paper.len() / LCD_WIDTH, &self.paper) {
+            Ok(())  => println!("Printed {}", path.display()),
+            Err(e)  => eprintln!("Cannot write {}: {}", path.display(), e),
+        }
+        self.paper.clear();
+    }
+}
+
+// 圧縮データの展開
+// 制御byteの最上位bitが1なら次の1byteを (n & 0x7F) + 2 回、0なら続く n + 1 byteをそのまま
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let n = data[i];
+        i += 1;
+        if n & 0x80 != 0 {
+            if let Some(&b) = data.get(i) {
+                out.extend(std::iter::repeat_n(b, (n & 0x7F) as usize + 2));
+            }
+            i += 1;
+        } else {
+            let end = (i + n as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, val: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            State::Magic1 => if val == 0x88 { State::Magic2 } else { State::Magic1 },
+            State::Magic2 => if val == 0x33 { State::Command } else { State::Magic1 },
+            State::Command => {
+                self.command = val;
+                State::Compression
+            },
+            State::Compression => {
+                self.compressed = val & 0x01 != 0;
+                State::LengthLo
+            },
+            State::LengthLo => {
+                self.length = val as usize;
+                State::LengthHi
+            },
+            State::LengthHi => {
+                self.length |= (val as usize) << 8;
+                self.data.clear();
+                if self.length > 0 { State::Data } else { State::ChecksumLo }
+            },
+            State::Data => {
+                self.data.push(val);
+                if self.data.len() >= self.length { State::ChecksumLo } else { State::Data }
+            },
+            State::ChecksumLo => {
+                self.checksum = val as u16;
+                State::ChecksumHi
+            },
+            State::ChecksumHi => {
+                self.checksum |= (val as u16) << 8;
+                self.execute(self.checksum);
+                State::Alive
+            },
+            State::Alive => {
+                reply = 0x81;
+                State::Status
+            },
+            State::Status => {
+                reply = self.status;
+                State::Magic1
+            },
+        };
+        reply
+    }
+
+    fn emulate_cycle(&mut self) {
+        if self.busy > 0 {
+            self.busy -= 1;
+            if self.busy == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+    }
+}
+
+impl Drop for Printer {
+    // 余白で区切られていない印刷結果も保存する
+    fn drop(&mut self) {
+        self.save();
+    }
+}
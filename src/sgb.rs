@@ -0,0 +1,552 @@
+// スーパーゲームボーイ
+// P1レジスタのパルスでコマンドパケット（16byte）を受け取り、パレット・枠を適用する
+// P14・P15を両方0でリセット、P14のみ0でbit0、P15のみ0でbit1、間に両方1を挟む
+
+use std::io;
+
+use crate::{
+    image::rgb888,
+    ppu::Ppu,
+    state::{invalid, Snapshot, StateReader, StateWriter},
+    LCD_HEIGHT,
+    LCD_WIDTH,
+};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+// ゲームボーイの画面の位置
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const PACKET_SIZE: usize = 16;
+const CELLS_X: usize = LCD_WIDTH / 8;     // 20
+const CELLS_Y: usize = LCD_HEIGHT / 8;    // 18
+const ATTR_FILE_SIZE: usize = CELLS_X * CELLS_Y / 4;   // 90byte
+
+// コマンド
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// 画面マスク
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    Cancel,
+    Freeze,     // 直前の画面を表示し続ける
+    Black,
+    Color0,
+}
+
+// 次のフレームで画面から転送するデータ
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    Tiles(bool),    // trueなら0x80～0xFFのタイル
+    Border,
+    Attributes,
+}
+
+pub struct Sgb {
+    // パケット受信
+    receiving: bool,
+    ready: bool,            // 両方1を受け取り、次のbitを待っている
+    bits: usize,
+    packet: [u8; PACKET_SIZE],
+    packets: Vec<[u8; PACKET_SIZE]>,
+
+    // 色
+    palettes: [[u16; 4]; 4],            // RGB555、色0は共通
+    system_palettes: Vec<[u16; 4]>,     // PAL_TRNで転送される512個
+    attributes: [u8; CELLS_X * CELLS_Y],    // 8x8のマス毎のパレット番号
+    attr_files: Vec<u8>,                // ATTR_TRNで転送される45個
+    mask: Mask,
+    frozen: Vec<u8>,
+
+    // 枠
+    border_tiles: Vec<u8>,              // 4bpp 256タイル
+    border_map: Vec<u16>,               // 32x32
+    border_palettes: [[u16; 16]; 4],    // パレット4～7
+
+    transfer: Option<Transfer>,
+
+    // マルチプレイヤー
+    players: u8,
+    player: u8,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        let gray = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+        Self {
+            receiving: false,
+            ready: false,
+            bits: 0,
+            packet: [0; PACKET_SIZE],
+            packets: Vec::new(),
+            palettes: [gray; 4],
+            system_palettes: vec![gray; 512],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attr_files: vec![0; ATTR_FILE_SIZE * 45],
+            mask: Mask::Cancel,
+            frozen: vec![0; LCD_WIDTH * LCD_HEIGHT],
+            border_tiles: vec![0; 0x2000],
+            border_map: vec![0; 32 * 32],
+            border_palettes: [[0; 16]; 4],
+            transfer: None,
+            players: 1,
+            player: 0,
+        }
+    }
+
+    // P1への書き込み（パケット受信）
+    pub fn write_p1(&mut self, old: u8, val: u8) {
+        // P15が0から1になるとプレイヤー番号が進む
+        if self.players > 1 && old & 0x20 == 0 && val & 0x20 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        match val & 0x30 {
+            // リセット
+            0x00 => {
+                self.receiving = true;
+                self.ready = false;
+                self.bits = 0;
+                self.packet = [0; PACKET_SIZE];
+            },
+            0x30 => self.ready = true,
+            bit if self.receiving && self.ready => {
+                self.ready = false;
+                if self.bits == PACKET_SIZE * 8 {
+                    // ストップビット
+                    self.receiving = false;
+                    self.receive_packet();
+                } else {
+                    if bit == 0x10 {
+                        self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+                    }
+                    self.bits += 1;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // P1読み込み時の下位4bit（マルチプレイヤー時は両方1でプレイヤー番号）
+    pub fn read_p1(&self, select: u8, low: u8) -> u8 {
+        if self.players > 1 && select == 0x30 {
+            0x0F - self.player
+        } else {
+            low
+        }
+    }
+
+    // 1パケット受け取り、コマンドの全パケットが揃えば実行する
+    fn receive_packet(&mut self) {
+        self.packets.push(self.packet);
+        let len = ((self.packets[0][0] & 0x07) as usize).max(1);
+        if self.packets.len() >= len {
+            let data: Vec<u8> = self.packets.concat();
+            self.packets.clear();
+            self.command(data[0] >> 3, &data);
+        }
+    }
+
+    fn command(&mut self, cmd: u8, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) & 0x7FFF;
+        match cmd {
+            PAL01 | PAL23 | PAL03 | PAL12 => {
+                let (p, q) = match cmd {
+                    PAL01 => (0, 1),
+                    PAL23 => (2, 3),
+                    PAL03 => (0, 3),
+                    _     => (1, 2),
+                };
+                for pal in self.palettes.iter_mut() {
+                    pal[0] = color(1);
+                }
+                for i in 0..3 {
+                    self.palettes[p][i + 1] = color(3 + i * 2);
+                    self.palettes[q][i + 1] = color(9 + i * 2);
+                }
+            },
+            ATTR_BLK => {
+                let count = (data[1] as usize).min(18);
+                for set in data[2..].chunks(6).take(count) {
+                    self.attr_block(set);
+                }
+            },
+            ATTR_LIN => {
+                let count = data[1] as usize;
+                for &line in data[2..].iter().take(count) {
+                    let pal = (line >> 5) & 0x03;
+                    let n = (line & 0x1F) as usize;
+                    if line & 0x80 != 0 {
+                        // 横線
+                        if n < CELLS_Y {
+                            self.attributes[n * CELLS_X..(n + 1) * CELLS_X].fill(pal);
+                        }
+                    } else if n < CELLS_X {
+                        // 縦線
+                        for y in 0..CELLS_Y {
+                            self.attributes[y * CELLS_X + n] = pal;
+                        }
+                    }
+                }
+            },
+            ATTR_DIV => {
+                let after = data[1] & 0x03;
+                let before = (data[1] >> 2) & 0x03;
+                let on = (data[1] >> 4) & 0x03;
+                let horizontal = data[1] & 0x40 != 0;
+                let line = data[2] as usize;
+                for y in 0..CELLS_Y {
+                    for x in 0..CELLS_X {
+                        let pos = if horizontal { y } else { x };
+                        self.attributes[y * CELLS_X + x] = match pos.cmp(&line) {
+                            std::cmp::Ordering::Less    => before,
+                            std::cmp::Ordering::Equal   => on,
+                            std::cmp::Ordering::Greater => after,
+                        };
+                    }
+                }
+            },
+            ATTR_CHR => {
+                let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+                let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(CELLS_X * CELLS_Y);
+                let vertical = data[5] != 0;
+                for i in 0..count {
+                    let Some(&byte) = data.get(6 + i / 4) else {
+                        break;
+                    };
+                    if x < CELLS_X && y < CELLS_Y {
+                        self.attributes[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+                    }
+                    if vertical {
+                        y += 1;
+                        if y >= CELLS_Y { y = 0; x += 1; }
+                    } else {
+                        x += 1;
+                        if x >= CELLS_X { x = 0; y += 1; }
+                    }
+                }
+            },
+            PAL_SET => {
+                for i in 0..4 {
+                    let n = (u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x1FF) as usize;
+                    self.palettes[i] = self.system_palettes[n];
+                }
+                // 色0は最初のパレットのものを共通で使う
+                let color0 = self.palettes[0][0];
+                for pal in self.palettes.iter_mut() {
+                    pal[0] = color0;
+                }
+                if data[9] & 0x80 != 0 {
+                    self.apply_attr_file((data[9] & 0x3F) as usize);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::Cancel;
+                }
+            },
+            ATTR_SET => {
+                self.apply_attr_file((data[1] & 0x3F) as usize);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::Cancel;
+                }
+            },
+            PAL_TRN  => self.transfer = Some(Transfer::Palettes),
+            CHR_TRN  => self.transfer = Some(Transfer::Tiles(data[1] & 0x01 != 0)),
+            PCT_TRN  => self.transfer = Some(Transfer::Border),
+            ATTR_TRN => self.transfer = Some(Transfer::Attributes),
+            MLT_REQ  => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            },
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    0 => Mask::Cancel,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            },
+            _ => (),    // 音・SNES側のプログラム関連は未対応
+        }
+    }
+
+    // ATTR_BLKの1ブロック
+    fn attr_block(&mut self, set: &[u8]) {
+        if set.len() < 6 {
+            return;
+        }
+        let ctrl = set[0] & 0x07;
+        let inside = set[1] & 0x03;
+        let mut border = (set[1] >> 2) & 0x03;
+        let outside = (set[1] >> 4) & 0x03;
+        // 内側・外側のみ指定された場合は境界も同じパレット
+        match ctrl {
+            0x01 => border = inside,
+            0x04 => border = outside,
+            _ => (),
+        }
+        let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let pal = if x > x1 && x < x2 && y > y1 && y < y2 {
+                    (ctrl & 0x01 != 0).then_some(inside)
+                } else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+                    (ctrl & 0x02 != 0 || ctrl == 0x01 || ctrl == 0x04).then_some(border)
+                } else {
+                    (ctrl & 0x04 != 0).then_some(outside)
+                };
+                if let Some(pal) = pal {
+                    self.attributes[y * CELLS_X + x] = pal;
+                }
+            }
+        }
+    }
+
+    // ATTR_TRNで転送したファイルの適用、1マス2bit
+    fn apply_attr_file(&mut self, file: usize) {
+        if file >= 45 {
+            return;
+        }
+        let data = &self.attr_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for (i, attr) in self.attributes.iter_mut().enumerate() {
+            *attr = (data[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    // フレームの終わり、VRAM転送とマスク用の画面保存
+    pub fn end_frame(&mut self, ppu: &Ppu) {
+        if self.mask != Mask::Freeze {
+            self.frozen.copy_from_slice(&ppu.shades);
+        }
+        let Some(transfer) = self.transfer.take() else {
+            return;
+        };
+        let data = ppu.screen_tile_data();
+        let color = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) & 0x7FFF;
+        match transfer {
+            Transfer::Palettes => {
+                for (i, pal) in self.system_palettes.iter_mut().enumerate() {
+                    for (j, c) in pal.iter_mut().enumerate() {
+                        *c = color(i * 8 + j * 2);
+                    }
+                }
+            },
+            Transfer::Tiles(high) => {
+                let start = if high { 0x1000 } else { 0 };
+                self.border_tiles[start..start + 0x1000].copy_from_slice(&data);
+            },
+            Transfer::Border => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+                }
+                for (i, pal) in self.border_palettes.iter_mut().enumerate() {
+                    for (j, c) in pal.iter_mut().enumerate() {
+                        *c = color(0x800 + i * 32 + j * 2);
+                    }
+                }
+            },
+            Transfer::Attributes => {
+                self.attr_files.copy_from_slice(&data[..ATTR_FILE_SIZE * 45]);
+            },
+        }
+    }
+
+    // 枠の1ピクセル（色番号0は透明）
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.border_map[(y / 8) * 32 + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let pal = ((entry >> 10) & 0x07) as usize;
+        let col = if entry & 0x4000 != 0 { x % 8 } else { 7 - x % 8 };
+        let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        // SNESの4bppタイル、bit0・1とbit2・3が16byteずつ交互に並ぶ
+        let base = tile * 32 + row * 2;
+        let t = &self.border_tiles;
+        let index = ((t[base] >> col) & 1)
+            | ((t[base + 1] >> col) & 1) << 1
+            | ((t[base + 16] >> col) & 1) << 2
+            | ((t[base + 17] >> col) & 1) << 3;
+        (index != 0 && (4..8).contains(&pal)).then(|| self.border_palettes[pal - 4][index as usize])
+    }
+
+    // 256x224のRGB888画像を作成する
+    pub fn render(&self, shades: &[u8]) -> Vec<u8> {
+        let shades = if self.mask == Mask::Freeze { &self.frozen[..] } else { shades };
+        let color0 = self.palettes[0][0];
+        let mut out = Vec::with_capacity(SGB_WIDTH * SGB_HEIGHT * 3);
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let in_screen = (SCREEN_X..SCREEN_X + LCD_WIDTH).contains(&x) && (SCREEN_Y..SCREEN_Y + LCD_HEIGHT).contains(&y);
+                let c = match self.border_pixel(x, y) {
+                    // 枠は画面より手前
+                    Some(c) => c,
+                    None if in_screen => {
+                        let (sx, sy) = (x - SCREEN_X, y - SCREEN_Y);
+                        match self.mask {
+                            Mask::Black  => 0x0000,
+                            Mask::Color0 => color0,
+                            _ => {
+                                let pal = self.attributes[(sy / 8) * CELLS_X + sx / 8] as usize;
+                                self.palettes[pal][shades[sy * LCD_WIDTH + sx] as usize]
+                            },
+                        }
+                    },
+                    None => color0,
+                };
+                out.extend(rgb888(c));
+            }
+        }
+        out
+    }
+}
+
+impl Mask {
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Mask::Cancel),
+            1 => Some(Mask::Freeze),
+            2 => Some(Mask::Black),
+            3 => Some(Mask::Color0),
+            _ => None,
+        }
+    }
+}
+
+impl Transfer {
+    fn to_u8(self) -> u8 {
+        match self {
+            Transfer::Palettes => 1,
+            Transfer::Tiles(false) => 2,
+            Transfer::Tiles(true) => 3,
+            Transfer::Border => 4,
+            Transfer::Attributes => 5,
+        }
+    }
+
+    fn from_u8(val: u8) -> Option<Option<Self>> {
+        match val {
+            0 => Some(None),
+            1 => Some(Some(Transfer::Palettes)),
+            2 => Some(Some(Transfer::Tiles(false))),
+            3 => Some(Some(Transfer::Tiles(true))),
+            4 => Some(Some(Transfer::Border)),
+            5 => Some(Some(Transfer::Attributes)),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for Sgb {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.receiving);
+        w.bool(self.ready);
+        w.usize(self.bits);
+        w.bytes(&self.packet);
+        w.usize(self.packets.len());
+        for packet in self.packets.iter() {
+            w.bytes(packet);
+        }
+
+        for color in self.palettes.iter().chain(self.system_palettes.iter()).flatten() {
+            w.u16(*color);
+        }
+        w.bytes(&self.attributes);
+        w.bytes(&self.attr_files);
+        w.u8(self.mask as u8);
+        w.bytes(&self.frozen);
+
+        w.bytes(&self.border_tiles);
+        for entry in self.border_map.iter().chain(self.border_palettes.iter().flatten()) {
+            w.u16(*entry);
+        }
+
+        w.u8(self.transfer.map_or(0, Transfer::to_u8));
+        w.u8(self.players);
+        w.u8(self.player);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.receiving = r.bool()?;
+        self.ready = r.bool()?;
+        self.bits = r.usize()?.min(PACKET_SIZE * 8);
+        r.bytes_into(&mut self.packet)?;
+        let count = r.usize()?;
+        self.packets.clear();
+        for _ in 0..count {
+            let mut packet = [0; PACKET_SIZE];
+            r.bytes_into(&mut packet)?;
+            self.packets.push(packet);
+        }
+
+        for color in self.palettes.iter_mut().chain(self.system_palettes.iter_mut()).flatten() {
+            *color = r.u16()?;
+        }
+        r.bytes_into(&mut self.attributes)?;
+        r.bytes_into(&mut self.attr_files)?;
+        self.mask = Mask::from_u8(r.u8()?).ok_or_else(|| invalid("invalid sgb mask"))?;
+        r.bytes_into(&mut self.frozen)?;
+
+        r.bytes_into(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut().chain(self.border_palettes.iter_mut().flatten()) {
+            *entry = r.u16()?;
+        }
+
+        self.transfer = Transfer::from_u8(r.u8()?).ok_or_else(|| invalid("invalid sgb transfer"))?;
+        self.players = r.u8()?;
+        self.player = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attributes[y * CELLS_X + x]
+    }
+
+    #[test]
+    fn attr_block_border() {
+        // 内側1、境界2、外側3、(2,2)～(6,6)のブロック
+        let mut sgb = Sgb::new();
+        sgb.attr_block(&[0x05, 0x39, 2, 2, 6, 6]);
+        assert_eq!(attr(&sgb, 4, 4), 1);
+        assert_eq!(attr(&sgb, 2, 4), 0);    // 境界は指定なし
+        assert_eq!(attr(&sgb, 0, 0), 3);
+
+        // 内側のみの指定は境界も内側のパレット
+        let mut sgb = Sgb::new();
+        sgb.attr_block(&[0x01, 0x39, 2, 2, 6, 6]);
+        assert_eq!(attr(&sgb, 4, 4), 1);
+        assert_eq!(attr(&sgb, 2, 4), 1);
+        assert_eq!(attr(&sgb, 0, 0), 0);
+
+        // 外側のみの指定は境界も外側のパレット
+        let mut sgb = Sgb::new();
+        sgb.attr_block(&[0x04, 0x39, 2, 2, 6, 6]);
+        assert_eq!(attr(&sgb, 4, 4), 0);
+        assert_eq!(attr(&sgb, 6, 6), 3);
+        assert_eq!(attr(&sgb, 19, 17), 3);
+    }
+}
@@ -0,0 +1,188 @@
+// ステートセーブ
+// ヘッダ：マジック、形式のバージョン、エミュレータのバージョン、ROMのSHA-1
+// 続いて各部品が save_state で書き込んだデータが並ぶ
+// CPUの命令途中の状態も含むため、どのMサイクルでも保存・読み込みできる
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha1_smol::Sha1;
+
+use crate::console::Console;
+
+const MAGIC: &[u8; 4] = b"GBST";
+pub const STATE_VERSION: u16 = 4;
+const EMULATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// 状態の保存・読み込み
+pub trait Snapshot {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()>;
+}
+
+pub fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.buf.push(val as u8);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, val: usize) {
+        self.u64(val as u64);
+    }
+
+    // 長さの付かないバイト列（マジックなど）
+    pub fn raw(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // 長さ付きのバイト列
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn raw(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let data = self.data.get(self.pos..self.pos + len).ok_or_else(|| invalid("state data is truncated"))?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.raw(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.raw(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.raw(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> io::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.raw(len)
+    }
+
+    // 長さが同じ領域へのバイト列の読み込み
+    pub fn bytes_into(&mut self, dst: &mut [u8]) -> io::Result<()> {
+        let data = self.bytes()?;
+        if data.len() != dst.len() {
+            return Err(invalid(format!("state size mismatch: {} != {}", data.len(), dst.len())));
+        }
+        dst.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+// 本体の状態を保存する
+pub fn save(console: &Console) -> Vec<u8> {
+    let mut w = StateWriter::default();
+    w.raw(MAGIC);
+    w.u16(STATE_VERSION);
+    w.bytes(EMULATOR_VERSION.as_bytes());
+    w.bytes(&Sha1::from(console.peripherals.cartridge.rom()).digest().bytes());
+    console.save_state(&mut w);
+    w.finish()
+}
+
+// ヘッダの確認
+fn read_header(r: &mut StateReader, console: &Console) -> io::Result<()> {
+    if r.raw(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let version = r.u16()?;
+    if version != STATE_VERSION {
+        return Err(invalid(format!("unsupported save state version {}", version)));
+    }
+    let _emulator_version = r.bytes()?;
+    if r.bytes()? != Sha1::from(console.peripherals.cartridge.rom()).digest().bytes() {
+        return Err(invalid("save state was made with a different rom"));
+    }
+    Ok(())
+}
+
+// 本体の状態を読み込む、途中で失敗した場合は読み込み前の状態に戻す
+pub fn load(console: &mut Console, data: &[u8]) -> io::Result<()> {
+    let mut r = StateReader::new(data);
+    read_header(&mut r, console)?;
+
+    let backup = save(console);
+    if let Err(e) = console.load_state(&mut r) {
+        let mut r = StateReader::new(&backup);
+        if read_header(&mut r, console).is_ok() {
+            let _ = console.load_state(&mut r);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+// スロット番号のファイル名（ROMと同じ名前で拡張子が .ss1 など）
+pub fn slot_path(base_path: &Path, slot: usize) -> PathBuf {
+    base_path.with_extension(format!("ss{}", slot))
+}
+
+pub fn save_slot(console: &Console, path: &Path) -> io::Result<()> {
+    // 書き込み途中で壊れないよう一時ファイルから置き換える
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, save(console))?;
+    fs::rename(&tmp, path)
+}
+
+pub fn load_slot(console: &mut Console, path: &Path) -> io::Result<()> {
+    load(console, &fs::read(path)?)
+}
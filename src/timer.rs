@@ -0,0 +1,154 @@
+// タイマー（DIV・TIMA・TMA・TAC）
+// 内部の16bitカウンタが1Mサイクルに4進み、DIVはその上位8bit
+// TIMAはTACで選んだカウンタのbitが1から0に変わる毎に進み、あふれるとTMAを読み込んで割り込み
+// あふれてからTMAを読み込むまでの1Mサイクルの遅れは省略
+
+use std::io;
+
+use crate::{
+    cpu::interrupts::{Interrupts, TIMER},
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const TIMER_ENABLE: u8 = 1 << 2;
+
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+        }
+    }
+
+    // 起動後のDIVの設定（ブートROMを飛ばす場合）
+    pub fn set_div(&mut self, div: u8) {
+        self.counter = (div as u16) << 8;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _      => panic!("Not Define {:x}", addr),
+        }
+    }
+
+    // DIVへの書き込みはカウンタを0にする
+    // DIV・TACの書き込みで選択中のbitが1から0に変わった場合もTIMAが進む
+    pub fn write(&mut self, interrupts: &mut Interrupts, addr: u16, val: u8) {
+        let before = self.signal();
+        match addr {
+            0xFF04 => self.counter = 0,
+            0xFF05 => self.tima = val,
+            0xFF06 => self.tma = val,
+            0xFF07 => self.tac = val & 0x07,
+            _      => panic!("Not Define {:x}", addr),
+        }
+        if before && !self.signal() {
+            self.increment(interrupts);
+        }
+    }
+
+    // TACで選んだカウンタのbit、4096Hz・262144Hz・65536Hz・16384Hz
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & TIMER_ENABLE > 0 && self.counter & (1 << bit) > 0
+    }
+
+    fn increment(&mut self, interrupts: &mut Interrupts) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        if overflow {
+            self.tima = self.tma;
+            interrupts.irq(TIMER);
+        } else {
+            self.tima = tima;
+        }
+    }
+
+    pub fn emulate_cycle(&mut self, interrupts: &mut Interrupts) {
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment(interrupts);
+        }
+    }
+}
+
+impl Snapshot for Timer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.counter = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tima() {
+        let mut timer = Timer::new();
+        let mut interrupts = Interrupts::default();
+        timer.write(&mut interrupts, 0xFF06, 0xF0);
+        timer.write(&mut interrupts, 0xFF05, 0xFE);
+        timer.write(&mut interrupts, 0xFF07, 0x05);     // 262144Hz、4Mサイクル毎
+        for _ in 0..4 {
+            timer.emulate_cycle(&mut interrupts);
+        }
+        assert_eq!(timer.read(0xFF05), 0xFF);
+        assert_eq!(interrupts.int_flags, 0);
+        for _ in 0..4 {
+            timer.emulate_cycle(&mut interrupts);
+        }
+        // あふれるとTMAを読み込んで割り込み
+        assert_eq!(timer.read(0xFF05), 0xF0);
+        assert_eq!(interrupts.int_flags, TIMER);
+        assert_eq!(timer.read(0xFF04), 0x00);
+        assert_eq!(timer.read(0xFF07), 0xFD);
+    }
+
+    #[test]
+    fn div_write() {
+        let mut timer = Timer::new();
+        let mut interrupts = Interrupts::default();
+        timer.set_div(0xAB);
+        assert_eq!(timer.read(0xFF04), 0xAB);
+        for _ in 0..64 {
+            timer.emulate_cycle(&mut interrupts);
+        }
+        assert_eq!(timer.read(0xFF04), 0xAC);
+
+        // 選択中のbitが1の時にDIVを0にするとTIMAが進む
+        timer.write(&mut interrupts, 0xFF07, 0x04);     // 4096Hz、カウンタのbit9
+        timer.set_div(0x02);
+        timer.write(&mut interrupts, 0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF04), 0x00);
+        assert_eq!(timer.read(0xFF05), 0x01);
+    }
+}
@@ -0,0 +1,39 @@
+// 8KBのワークRAM
+// 0xE000-0xFDFF は 0xC000-0xDDFF のミラー
+
+use std::io;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+
+pub struct WRam {
+    wram: Vec<u8>,           // u8の配列
+}
+
+impl WRam {
+  pub fn new() -> Self {
+    Self{
+        wram: vec![0; 0x2000]
+    }
+  }
+
+  // RAM 1byte読み出し
+  pub fn read(&self, addr: u16) -> u8 {
+    self.wram[(addr as usize) & 0x1FFF]
+  }
+
+  // RAM 1byte書き込み
+  pub fn write(&mut self, addr: u16, val: u8) {
+    self.wram[(addr as usize) & 0x1FFF] = val;
+  }
+}
+
+impl Snapshot for WRam {
+  fn save_state(&self, w: &mut StateWriter) {
+    w.bytes(&self.wram);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+    r.bytes_into(&mut self.wram)
+  }
+}